@@ -1,21 +1,7 @@
-(define even
-  (lambda (n)
-    (int (/ n 2))))
-
-(define >=
-  (lambda (a b)
-    (not (< a b))))
-
-(define max
-  (lambda (a b)
-    (if (>= a b)
-        a
-        b)))
-
 (define collatz-helper
   (lambda (n sum)
     (if (= n 1) sum
-        (if (even n)
+        (if (even? n)
             (collatz-helper (/ n 2) (+ sum 1))
             (collatz-helper (/ (+ (* 3 n) 1) 2) (+ sum 2))))))
 
@@ -30,3 +16,4 @@
         (search (+ n 1) (max (collatz n) m)))))
 
 (display (search 50 0))
+(newline)
@@ -1,26 +1,12 @@
-(define length
-  (lambda (l)
-    (if (null? l)
-        0
-        (+ 1 (length (cdr l))))))
-
-(define map
-  (lambda (f l)
-    (if (null? l)
-        ()
-        (cons (f (car l)) (map f (cdr l))))))
-
-(define filter
-  (lambda (pred l)
-    (if (null? l) ()
-        (if (pred (car l))
-            (cons (car l) (filter pred (cdr l)))
-            (filter pred (cdr l))))))
-
-(define even?
-  (lambda (n)
-    (int (/ n 2))))
-
 (display (length '(1 2 3 4 5)))
+(newline)
 (display (map (lambda (x) (+ x 1)) (list 1 2 3)))
+(newline)
 (display (filter even? '(0 1 2 3 4 5 6)))
+(newline)
+(display (fold-left + 0 (iota 10)))
+(newline)
+(display (sort '(3 1 4 1 5 9 2 6) <))
+(newline)
+(display (assq 'b '((a . 1) (b . 2))))
+(newline)
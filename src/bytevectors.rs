@@ -0,0 +1,387 @@
+use crate::eval::{index_arg, out_of_range, range_args, Builtin, Value};
+use crate::exceptions::Exception;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "bytevector" => |args, _| {
+            let bytes = args
+                .iter()
+                .map(|v| byte("bytevector", v))
+                .collect::<Result<Vec<u8>, Exception>>()?;
+            Ok(new_bytevector(bytes))
+        },
+        "make-bytevector" => make_bytevector,
+        "bytevector-length" => |args, _| match args {
+            [Value::Bytevector(b)] => Ok(Value::Integer(b.borrow().len() as isize)),
+            _ => Err(format!(
+                "Expected one bytevector to `bytevector-length`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "bytevector-u8-ref" => |args, _| int_ref("bytevector-u8-ref", args, 1, false),
+        "bytevector-u8-set!" => |args, _| int_set("bytevector-u8-set!", args, 1, false),
+        "bytevector-copy" => bytevector_copy,
+        "bytevector-copy!" => bytevector_copy_to,
+        "bytevector-append" => bytevector_append,
+        "bytevector-fill!" => bytevector_fill,
+        "utf8->string" => utf8_to_string,
+        "string->utf8" => string_to_utf8,
+        "bytevector-u16-ref" => |args, _| int_ref("bytevector-u16-ref", args, 2, false),
+        "bytevector-s16-ref" => |args, _| int_ref("bytevector-s16-ref", args, 2, true),
+        "bytevector-u32-ref" => |args, _| int_ref("bytevector-u32-ref", args, 4, false),
+        "bytevector-s32-ref" => |args, _| int_ref("bytevector-s32-ref", args, 4, true),
+        "bytevector-u64-ref" => |args, _| int_ref("bytevector-u64-ref", args, 8, false),
+        "bytevector-s64-ref" => |args, _| int_ref("bytevector-s64-ref", args, 8, true),
+        "bytevector-u16-set!" => |args, _| int_set("bytevector-u16-set!", args, 2, false),
+        "bytevector-s16-set!" => |args, _| int_set("bytevector-s16-set!", args, 2, true),
+        "bytevector-u32-set!" => |args, _| int_set("bytevector-u32-set!", args, 4, false),
+        "bytevector-s32-set!" => |args, _| int_set("bytevector-s32-set!", args, 4, true),
+        "bytevector-u64-set!" => |args, _| int_set("bytevector-u64-set!", args, 8, false),
+        "bytevector-s64-set!" => |args, _| int_set("bytevector-s64-set!", args, 8, true),
+        "bytevector-ieee-single-ref" => |args, _| float_ref("bytevector-ieee-single-ref", args, 4),
+        "bytevector-ieee-double-ref" => |args, _| float_ref("bytevector-ieee-double-ref", args, 8),
+        "bytevector-ieee-single-set!" => {
+            |args, _| float_set("bytevector-ieee-single-set!", args, 4)
+        }
+        "bytevector-ieee-double-set!" => {
+            |args, _| float_set("bytevector-ieee-double-set!", args, 8)
+        }
+        _ => return None,
+    };
+    Some(f)
+}
+
+pub fn new_bytevector(bytes: Vec<u8>) -> Value {
+    Value::Bytevector(Rc::new(RefCell::new(bytes)))
+}
+
+fn byte(name: &str, v: &Value) -> Result<u8, Exception> {
+    match v {
+        Value::Integer(n) if (0..=255).contains(n) => Ok(*n as u8),
+        v => Err(format!("Expected a byte to `{}`, got {:#?}", name, v).into()),
+    }
+}
+
+fn bytevector<'a>(name: &str, v: &'a Value) -> Result<&'a Rc<RefCell<Vec<u8>>>, Exception> {
+    match v {
+        Value::Bytevector(b) => Ok(b),
+        v => Err(format!("Expected a bytevector to `{}`, got {:#?}", name, v).into()),
+    }
+}
+
+/// Checks that `v` is an index into a bytevector of length `len`. `end` allows `len` itself,
+/// for the end of a range.
+/// `(make-bytevector k [fill])`
+fn make_bytevector(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Integer(k), rest @ ..] if *k >= 0 && rest.len() <= 1 => {
+            let fill = match rest.first() {
+                Some(fill) => byte("make-bytevector", fill)?,
+                None => 0,
+            };
+            Ok(new_bytevector(vec![fill; *k as usize]))
+        }
+        _ => Err(format!(
+            "Expected a length and an optional byte to `make-bytevector`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(bytevector-copy bv [start [end]])`
+fn bytevector_copy(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [b, range @ ..] if range.len() <= 2 => {
+            let b = bytevector("bytevector-copy", b)?.borrow();
+            let (start, end) = range_args("bytevector-copy", range, b.len())?;
+            Ok(new_bytevector(b[start..end].to_vec()))
+        }
+        _ => Err(format!(
+            "Expected a bytevector and an optional range to `bytevector-copy`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(bytevector-copy! to at from [start [end]])` copies bytes of `from` into `to` at index `at`.
+fn bytevector_copy_to(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [to, at, from, range @ ..] if range.len() <= 2 => {
+            // Copied first, in case `to` and `from` are the same bytevector
+            let from = bytevector("bytevector-copy!", from)?.borrow().clone();
+            let (start, end) = range_args("bytevector-copy!", range, from.len())?;
+            let mut to = bytevector("bytevector-copy!", to)?.borrow_mut();
+            let at = index_arg("bytevector-copy!", at, to.len(), true)?;
+            if at + (end - start) > to.len() {
+                return Err(format!(
+                    "Not enough room to copy {} bytes to index {} of a bytevector of length {}",
+                    end - start,
+                    at,
+                    to.len()
+                ).into());
+            }
+            to[at..at + (end - start)].copy_from_slice(&from[start..end]);
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a bytevector, an index, a bytevector and an optional range to `bytevector-copy!`, got {:#?}",
+            args
+        ).into()),
+    }
+}
+
+fn bytevector_append(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let mut res = Vec::new();
+    for b in args {
+        res.extend_from_slice(&bytevector("bytevector-append", b)?.borrow());
+    }
+    Ok(new_bytevector(res))
+}
+
+/// `(bytevector-fill! bv byte [start [end]])`
+fn bytevector_fill(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [b, fill, range @ ..] if range.len() <= 2 => {
+            let fill = byte("bytevector-fill!", fill)?;
+            let mut b = bytevector("bytevector-fill!", b)?.borrow_mut();
+            let (start, end) = range_args("bytevector-fill!", range, b.len())?;
+            b[start..end].iter_mut().for_each(|x| *x = fill);
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a bytevector, a byte and an optional range to `bytevector-fill!`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(utf8->string bv [start [end]])` decodes bytes, which must be valid UTF-8.
+fn utf8_to_string(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [b, range @ ..] if range.len() <= 2 => {
+            let b = bytevector("utf8->string", b)?.borrow();
+            let (start, end) = range_args("utf8->string", range, b.len())?;
+            match std::str::from_utf8(&b[start..end]) {
+                Ok(s) => Ok(Value::String(s.to_string())),
+                Err(e) => Err(format!("Invalid UTF-8 in `utf8->string`: {}", e).into()),
+            }
+        }
+        _ => Err(format!(
+            "Expected a bytevector and an optional range to `utf8->string`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(string->utf8 s [start [end]])` encodes the chars of `s` from `start` to `end`.
+fn string_to_utf8(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::String(s), range @ ..] if range.len() <= 2 => {
+            let chars = s.chars().collect::<Vec<char>>();
+            let (start, end) = range_args("string->utf8", range, chars.len())?;
+            let s = chars[start..end].iter().collect::<String>();
+            Ok(new_bytevector(s.into_bytes()))
+        }
+        _ => Err(format!(
+            "Expected a string and an optional range to `string->utf8`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// Whether the symbol `v` asks for big-endian rather than little-endian byte order. Single
+/// bytes have no order, so it is optional for them.
+fn big_endian(name: &str, v: Option<&Value>, size: usize) -> Result<bool, Exception> {
+    match v {
+        Some(Value::Symbol(s)) if s == "big" => Ok(true),
+        Some(Value::Symbol(s)) if s == "little" => Ok(false),
+        None if size == 1 => Ok(false),
+        v => Err(format!("Expected `big` or `little` to `{}`, got {:#?}", name, v).into()),
+    }
+}
+
+/// Reads `size` bytes at index `k` of a bytevector, in little-endian order.
+fn read_bytes(
+    name: &str,
+    b: &Value,
+    k: &Value,
+    size: usize,
+    big: bool,
+) -> Result<[u8; 8], Exception> {
+    let b = bytevector(name, b)?.borrow();
+    let k = index_arg(name, k, b.len(), false)?;
+    if k + size > b.len() {
+        return Err(out_of_range(name, k as isize, b.len()));
+    }
+    let mut buf = [0; 8];
+    buf[..size].copy_from_slice(&b[k..k + size]);
+    if big {
+        buf[..size].reverse();
+    }
+    Ok(buf)
+}
+
+/// Writes the first `size` of some little-endian bytes at index `k` of a bytevector.
+fn write_bytes(
+    name: &str,
+    b: &Value,
+    k: &Value,
+    size: usize,
+    big: bool,
+    mut bytes: [u8; 8],
+) -> Result<Value, Exception> {
+    let mut b = bytevector(name, b)?.borrow_mut();
+    let k = index_arg(name, k, b.len(), false)?;
+    if k + size > b.len() {
+        return Err(out_of_range(name, k as isize, b.len()));
+    }
+    if big {
+        bytes[..size].reverse();
+    }
+    b[k..k + size].copy_from_slice(&bytes[..size]);
+    Ok(Value::Nil)
+}
+
+/// `(bytevector-u16-ref bv k endianness)` and friends read a `size`-byte integer.
+fn int_ref(name: &str, args: &[Value], size: usize, signed: bool) -> Result<Value, Exception> {
+    match args {
+        [b, k, rest @ ..] if rest.len() <= 1 => {
+            let big = big_endian(name, rest.first(), size)?;
+            let n = u64::from_le_bytes(read_bytes(name, b, k, size, big)?);
+            let unused = 64 - 8 * size as u32;
+            let n = if signed {
+                // Sign-extends from the top bit that was read
+                isize::try_from(((n << unused) as i64) >> unused).ok()
+            } else {
+                isize::try_from(n).ok()
+            };
+            n.map(Value::Integer)
+                .ok_or_else(|| format!("Result of `{}` doesn't fit in an integer", name).into())
+        }
+        _ => Err(format!(
+            "Expected a bytevector, an index and an endianness to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// `(bytevector-u16-set! bv k n endianness)` and friends write a `size`-byte integer.
+fn int_set(name: &str, args: &[Value], size: usize, signed: bool) -> Result<Value, Exception> {
+    match args {
+        [b, k, Value::Integer(n), rest @ ..] if rest.len() <= 1 => {
+            let big = big_endian(name, rest.first(), size)?;
+            let bits = 8 * size as u32;
+            let n = *n as i128;
+            let (min, max) = if signed {
+                (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+            } else {
+                (0, (1 << bits) - 1)
+            };
+            if n < min || n > max {
+                return Err(format!("{} doesn't fit in {} bytes for `{}`", n, size, name).into());
+            }
+            write_bytes(name, b, k, size, big, (n as i64).to_le_bytes())
+        }
+        _ => Err(format!(
+            "Expected a bytevector, an index, an integer and an endianness to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// `(bytevector-ieee-single-ref bv k endianness)` and the double version read a float.
+fn float_ref(name: &str, args: &[Value], size: usize) -> Result<Value, Exception> {
+    match args {
+        [b, k, endianness] => {
+            let big = big_endian(name, Some(endianness), size)?;
+            let buf = read_bytes(name, b, k, size, big)?;
+            Ok(Value::Number(if size == 4 {
+                f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64
+            } else {
+                f64::from_le_bytes(buf)
+            }))
+        }
+        _ => Err(format!(
+            "Expected a bytevector, an index and an endianness to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+fn float_set(name: &str, args: &[Value], size: usize) -> Result<Value, Exception> {
+    let (b, k, x, endianness) = match args {
+        [b, k, Value::Number(x), endianness] => (b, k, *x, endianness),
+        [b, k, Value::Integer(n), endianness] => (b, k, *n as f64, endianness),
+        _ => {
+            return Err(format!(
+            "Expected a bytevector, an index, a real number and an endianness to `{}`, got {:#?}",
+            name, args
+        )
+            .into())
+        }
+    };
+    let big = big_endian(name, Some(endianness), size)?;
+    let mut bytes = [0; 8];
+    if size == 4 {
+        bytes[..4].copy_from_slice(&(x as f32).to_le_bytes());
+    } else {
+        bytes = x.to_le_bytes();
+    }
+    write_bytes(name, b, k, size, big, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn bytes_and_text() {
+        assert_eq!(run("(string->utf8 \"hé\")"), Ok("#u8(104 195 169)".into()));
+        assert_eq!(run("(utf8->string #u8(104 195 169))"), Ok("\"hé\"".into()));
+        assert!(run("(utf8->string #u8(255))").is_err());
+        assert!(run("#u8(256)").is_err());
+    }
+
+    #[test]
+    fn copying_and_appending() {
+        assert_eq!(
+            run("(list (bytevector-append #u8(1) #u8(2 3)) (bytevector-copy #u8(1 2 3) 1) (make-bytevector 2 7))"),
+            Ok("(#u8(1 2 3) #u8(2 3) #u8(7 7))".into())
+        );
+        assert_eq!(
+            run("(define c (bytevector 1 2 3 4)) (bytevector-copy! c 1 #u8(9 9)) c"),
+            Ok("#u8(1 9 9 4)".into())
+        );
+        assert!(run("(bytevector-u8-ref #u8(1) 1)").is_err());
+    }
+
+    #[test]
+    fn multi_byte_numbers() {
+        assert_eq!(
+            run("(list (bytevector-s16-ref #u8(255 254) 0 'big) (bytevector-u16-ref #u8(1 2) 0 'little))"),
+            Ok("(-2 513)".into())
+        );
+        assert_eq!(
+            run("(define b (make-bytevector 8 0)) (bytevector-ieee-double-set! b 0 1.5 'little) (bytevector-ieee-double-ref b 0 'little)"),
+            Ok("1.5".into())
+        );
+        assert_eq!(
+            run("(define b (make-bytevector 4 0)) (bytevector-s32-set! b 0 -2 'big) b"),
+            Ok("#u8(255 255 255 254)".into())
+        );
+        assert!(run("(bytevector-u32-ref #u8(1 2 3) 0 'big)").is_err());
+    }
+}
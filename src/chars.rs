@@ -0,0 +1,177 @@
+use crate::eval::{Builtin, Value};
+use crate::exceptions::Exception;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "char?" => is_char,
+        "char->integer" => char_to_integer,
+        "integer->char" => integer_to_char,
+        "char-upcase" => |args, _| map_char("char-upcase", args, upcase),
+        "char-downcase" => |args, _| map_char("char-downcase", args, downcase),
+        "char-foldcase" => |args, _| map_char("char-foldcase", args, downcase),
+        "char-alphabetic?" => |args, _| test_char("char-alphabetic?", args, char::is_alphabetic),
+        "char-numeric?" => |args, _| test_char("char-numeric?", args, char::is_numeric),
+        "char-whitespace?" => |args, _| test_char("char-whitespace?", args, char::is_whitespace),
+        "char-upper-case?" => |args, _| test_char("char-upper-case?", args, char::is_uppercase),
+        "char-lower-case?" => |args, _| test_char("char-lower-case?", args, char::is_lowercase),
+        "digit-value" => digit_value,
+        "char=?" => |args, _| compare("char=?", args, false, |a, b| a == b),
+        "char<?" => |args, _| compare("char<?", args, false, |a, b| a < b),
+        "char>?" => |args, _| compare("char>?", args, false, |a, b| a > b),
+        "char<=?" => |args, _| compare("char<=?", args, false, |a, b| a <= b),
+        "char>=?" => |args, _| compare("char>=?", args, false, |a, b| a >= b),
+        "char-ci=?" => |args, _| compare("char-ci=?", args, true, |a, b| a == b),
+        "char-ci<?" => |args, _| compare("char-ci<?", args, true, |a, b| a < b),
+        "char-ci>?" => |args, _| compare("char-ci>?", args, true, |a, b| a > b),
+        "char-ci<=?" => |args, _| compare("char-ci<=?", args, true, |a, b| a <= b),
+        "char-ci>=?" => |args, _| compare("char-ci>=?", args, true, |a, b| a >= b),
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// How a character is written after `#\`, using its name if it has one.
+pub fn char_name(c: char) -> String {
+    match c {
+        ' ' => "space".to_string(),
+        '\n' => "newline".to_string(),
+        '\t' => "tab".to_string(),
+        '\r' => "return".to_string(),
+        '\0' => "null".to_string(),
+        '\u{7}' => "alarm".to_string(),
+        '\u{8}' => "backspace".to_string(),
+        '\u{1b}' => "escape".to_string(),
+        '\u{7f}' => "delete".to_string(),
+        c if c.is_control() => format!("x{:x}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+/// Changes case only when it maps to exactly one character, so e.g. `ß` is left alone.
+pub fn upcase(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+pub fn downcase(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+fn one_char(name: &str, args: &[Value]) -> Result<char, Exception> {
+    match args {
+        [Value::Char(c)] => Ok(*c),
+        _ => Err(format!("Expected one char argument to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+fn is_char(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [v] => Ok(Value::Bool(matches!(v, Value::Char(_)))),
+        _ => Err(format!("Expected one argument to `char?`, got {:#?}", args).into()),
+    }
+}
+
+fn char_to_integer(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    Ok(Value::Integer(one_char("char->integer", args)? as isize))
+}
+
+fn integer_to_char(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Integer(n)] => u32::try_from(*n)
+            .ok()
+            .and_then(std::char::from_u32)
+            .map(Value::Char)
+            .ok_or_else(|| format!("{} is not a Unicode scalar value!", n).into()),
+        _ => Err(format!(
+            "Expected one integer argument to `integer->char`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn map_char(name: &str, args: &[Value], f: fn(char) -> char) -> Result<Value, Exception> {
+    Ok(Value::Char(f(one_char(name, args)?)))
+}
+
+fn test_char(name: &str, args: &[Value], f: fn(char) -> bool) -> Result<Value, Exception> {
+    Ok(Value::Bool(f(one_char(name, args)?)))
+}
+
+/// The value of a decimal digit, or `#f` if the char isn't one.
+fn digit_value(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    Ok(one_char("digit-value", args)?
+        .to_digit(10)
+        .map(|d| Value::Integer(d as isize))
+        .unwrap_or(Value::Bool(false)))
+}
+
+/// True if every adjacent pair of chars satisfies `f`, comparing case-folded chars if `ci`.
+fn compare(
+    name: &str,
+    args: &[Value],
+    ci: bool,
+    f: fn(char, char) -> bool,
+) -> Result<Value, Exception> {
+    let chars = args
+        .iter()
+        .map(|v| match v {
+            Value::Char(c) if ci => Ok(downcase(*c)),
+            Value::Char(c) => Ok(*c),
+            v => Err(format!("Expected char arguments to `{}`, got {:#?}", name, v).into()),
+        })
+        .collect::<Result<Vec<char>, Exception>>()?;
+    Ok(Value::Bool(chars.windows(2).all(|w| f(w[0], w[1]))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(
+            run("(list #\\space #\\x3bb #\\newline #\\nul #\\a)"),
+            Ok("(#\\space #\\λ #\\newline #\\null #\\a)".into())
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(
+            run("(list (char->integer #\\A) (integer->char 955))"),
+            Ok("(65 #\\λ)".into())
+        );
+        assert_eq!(
+            run("(list (char-upcase #\\a) (char-downcase #\\Λ) (char-foldcase #\\A))"),
+            Ok("(#\\A #\\λ #\\a)".into())
+        );
+        assert_eq!(
+            run("(list (digit-value #\\7) (digit-value #\\a))"),
+            Ok("(7 #f)".into())
+        );
+        assert!(run("(integer->char 55296)").is_err());
+    }
+
+    #[test]
+    fn classes_and_comparisons() {
+        assert_eq!(
+            run("(list (char-alphabetic? #\\λ) (char-numeric? #\\5) (char-whitespace? #\\tab) (char-upper-case? #\\a))"),
+            Ok("(#t #t #t #f)".into())
+        );
+        assert_eq!(
+            run("(list (char<? #\\a #\\b #\\c) (char<? #\\a #\\c #\\b) (char-ci=? #\\a #\\A))"),
+            Ok("(#t #f #t)".into())
+        );
+        assert!(run("(char<? #\\a 1)").is_err());
+    }
+}
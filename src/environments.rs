@@ -0,0 +1,170 @@
+use crate::eval::{eval_expression, Builtin, Value};
+use crate::exceptions::Exception;
+use crate::parser::{datum_to_expression, Expression};
+use crate::prelude;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "eval" => |args, _| match args {
+            [datum, Value::Environment(Environment::Interaction)] => {
+                let expr = datum_to_expression(datum)?;
+                with_top_level(program(), || eval_top_level(&expr))
+            }
+            [datum, Value::Environment(Environment::Sandbox(bindings))] => {
+                let outside = SANDBOXED.with(|sandboxed| sandboxed.replace(true));
+                let res = datum_to_expression(datum)
+                    .and_then(|expr| with_top_level(bindings.clone(), || eval_top_level(&expr)));
+                SANDBOXED.with(|sandboxed| sandboxed.set(outside));
+                res
+            }
+            _ => Err(format!(
+                "Expected a datum and an environment to `eval`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "interaction-environment" => |args, _| match args {
+            [] => Ok(Value::Environment(Environment::Interaction)),
+            _ => Err(format!(
+                "Expected no arguments to `interaction-environment`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "scheme-report-environment" => |args, _| match args {
+            [Value::Integer(5 | 7)] => Ok(sandbox(prelude::environment()?)),
+            _ => Err(format!(
+                "Expected the version 5 or 7 to `scheme-report-environment`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "sandbox-environment" => |args, _| match args {
+            [] => Ok(sandbox(HashMap::new())),
+            _ => Err(format!(
+                "Expected no arguments to `sandbox-environment`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// Where `eval` evaluates its datum.
+#[derive(Clone)]
+pub enum Environment {
+    /// The program's top level, so definitions are seen by the rest of the program, wherever
+    /// `eval` is called from
+    Interaction,
+    /// Bindings of their own, starting with the builtins that can't reach files and, for
+    /// `scheme-report-environment`, the prelude. Definitions made by one `eval` are kept for
+    /// later ones in the same environment.
+    Sandbox(Rc<RefCell<HashMap<String, Value>>>),
+}
+
+/// Builtins that reach outside of the program, to files or to its top level, which code
+/// evaluated in a sandbox can't see.
+const UNSAFE: &[&str] = &[
+    "open-input-file",
+    "open-output-file",
+    "call-with-input-file",
+    "call-with-output-file",
+    "with-output-to-file",
+    "load",
+    "interaction-environment",
+];
+
+thread_local! {
+    /// Whether the code running was passed to `eval` with a sandbox environment.
+    static SANDBOXED: Cell<bool> = const { Cell::new(false) };
+    /// The bindings of the program's top level, which `(interaction-environment)` stands for.
+    static PROGRAM: Rc<RefCell<HashMap<String, Value>>> = Rc::default();
+    /// The top level of the running code: the program's, or that of the sandbox, library or
+    /// prelude being evaluated. Names that aren't bound in a procedure are looked up here.
+    static TOP_LEVEL: RefCell<Rc<RefCell<HashMap<String, Value>>>> =
+        RefCell::new(PROGRAM.with(Rc::clone));
+}
+
+/// Whether the running code is in a sandbox, where files can't be read.
+pub fn sandboxed() -> bool {
+    SANDBOXED.with(Cell::get)
+}
+
+/// Whether the builtin called `name` can be used by the running code.
+pub fn visible(name: &str) -> bool {
+    !(sandboxed() && UNSAFE.contains(&name))
+}
+
+/// The bindings of the program's top level.
+pub fn program() -> Rc<RefCell<HashMap<String, Value>>> {
+    PROGRAM.with(Rc::clone)
+}
+
+/// The value bound to `name` at the top level of the running code, if any.
+pub fn global(name: &str) -> Option<Value> {
+    TOP_LEVEL.with(|top| top.borrow().borrow().get(name).cloned())
+}
+
+/// Runs `f` with `top` as the top level, whose bindings it sees and adds to.
+pub fn with_top_level<T>(top: Rc<RefCell<HashMap<String, Value>>>, f: impl FnOnce() -> T) -> T {
+    let outside = TOP_LEVEL.with(|current| current.replace(top));
+    let res = f();
+    TOP_LEVEL.with(|current| *current.borrow_mut() = outside);
+    res
+}
+
+/// Evaluates a top-level form in a scope of its own, then adds the definitions it made to the
+/// top level, where the rest of the program sees them.
+pub fn eval_top_level(expr: &Expression) -> Result<Value, Exception> {
+    let mut scope = HashMap::new();
+    let res = eval_expression(expr, &mut scope);
+    let top = TOP_LEVEL.with(|top| top.borrow().clone());
+    top.borrow_mut().extend(scope);
+    res
+}
+
+/// Environments can contain themselves, so their bindings are left out.
+impl std::fmt::Debug for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Environment::Interaction => write!(f, "#<interaction-environment>"),
+            Environment::Sandbox(_) => write!(f, "#<environment>"),
+        }
+    }
+}
+
+fn sandbox(bindings: HashMap<String, Value>) -> Value {
+    Value::Environment(Environment::Sandbox(Rc::new(RefCell::new(bindings))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn eval_defines_at_the_top_level() {
+        let defining = "(define f (lambda () (eval '(define z 1) (interaction-environment))))";
+        assert_eq!(run(&format!("{} (f) z", defining)), Ok("1".into()));
+        assert_eq!(
+            run("(define x 'a) (define g (lambda () (eval '(define x 'a) (interaction-environment)))) (define x 'b) (g) x"),
+            Ok("a".into())
+        );
+    }
+
+    #[test]
+    fn sandboxes_keep_their_definitions_apart() {
+        assert_eq!(
+            run("(define s (sandbox-environment)) (eval '(define w 3) s) (eval '(* w 2) s)"),
+            Ok("6".into())
+        );
+        assert!(run("(define s (sandbox-environment)) (eval '(define w 3) s) w").is_err());
+        assert!(run("(define w 3) (eval 'w (sandbox-environment))").is_err());
+        assert!(run("(eval '(load \"x.scm\") (sandbox-environment))").is_err());
+        assert!(run("(eval '(interaction-environment) (sandbox-environment))").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::environments::Environment;
+use crate::eval::{Builtin, Value};
+use crate::exceptions::Exception;
+use crate::hashtables::HashKey;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "eq?" => |args, _| compare("eq?", eq, args),
+        "eqv?" => |args, _| compare("eqv?", eqv, args),
+        "equal?" => |args, _| compare("equal?", equal, args),
+        _ => return None,
+    };
+    Some(f)
+}
+
+fn compare(name: &str, f: fn(&Value, &Value) -> bool, args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [a, b] => Ok(Value::Bool(f(a, b))),
+        _ => Err(format!("Expected two arguments to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+/// Whether `a` and `b` are the very same object. Pairs, vectors, tables, records, procedures
+/// and persistent collections are compared by pointer. Atoms like numbers, chars, symbols and
+/// strings are values here with nothing else to compare, so they are compared like `eqv`.
+pub fn eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::PMap(x), Value::PMap(y)) | (Value::PSet(x), Value::PSet(y)) => x.ptr_eq(y),
+        (Value::PVector(x), Value::PVector(y)) => x.ptr_eq(y),
+        _ => eqv(a, b),
+    }
+}
+
+/// Whether `a` and `b` are the same atom, or the very same shared object. Numbers must agree
+/// in exactness too, so `1` and `1.0` differ. Strings and persistent collections can't be
+/// changed, so there is no way to tell equal ones apart and they are compared by contents.
+pub fn eqv(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+        (Value::Complex(a, b), Value::Complex(c, d)) => {
+            a.to_bits() == c.to_bits() && b.to_bits() == d.to_bits()
+        }
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Char(a), Value::Char(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Symbol(a), Value::Symbol(b)) => a == b,
+        (Value::Builtin(a), Value::Builtin(b)) => a == b,
+        (Value::Function(_, a), Value::Function(_, b)) => Rc::ptr_eq(a, b),
+        (Value::Pair(a), Value::Pair(b)) => Rc::ptr_eq(a, b),
+        (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+        (Value::Bytevector(a), Value::Bytevector(b)) => Rc::ptr_eq(a, b),
+        (Value::HashTable(a), Value::HashTable(b)) => Rc::ptr_eq(a, b),
+        (Value::Record(a), Value::Record(b)) => Rc::ptr_eq(a, b),
+        (Value::RecordProcedure(a), Value::RecordProcedure(b)) => Rc::ptr_eq(a, b),
+        (Value::Port(a), Value::Port(b)) => Rc::ptr_eq(a, b),
+        (Value::Eof, Value::Eof) => true,
+        (Value::Error(a), Value::Error(b)) => Rc::ptr_eq(a, b),
+        (Value::Environment(a), Value::Environment(b)) => match (a, b) {
+            (Environment::Interaction, Environment::Interaction) => true,
+            (Environment::Sandbox(a), Environment::Sandbox(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        },
+        (Value::PMap(x), Value::PMap(y)) | (Value::PSet(x), Value::PSet(y)) => {
+            same_contents(a, b, x.ptr_eq(y))
+        }
+        (Value::PVector(x), Value::PVector(y)) => same_contents(a, b, x.ptr_eq(y)),
+        (Value::Nil, Value::Nil) => true,
+        _ => false,
+    }
+}
+
+/// Whether two persistent collections have the same contents. Ones holding circular structure
+/// have no key to compare, so they are only the same if they are `identical`.
+fn same_contents(a: &Value, b: &Value, identical: bool) -> bool {
+    match (HashKey::new(a), HashKey::new(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => identical,
+    }
+}
+
+/// Whether `a` and `b` have the same structure: pairs, vectors and bytevectors are compared
+/// element by element, and everything else by `eqv`. Circular structures are handled by assuming that
+/// two objects already being compared are equal.
+pub fn equal(a: &Value, b: &Value) -> bool {
+    let mut seen = HashSet::new();
+    let mut pending = vec![(a.clone(), b.clone())];
+    while let Some((a, b)) = pending.pop() {
+        match (&a, &b) {
+            (Value::Pair(x), Value::Pair(y)) => {
+                if !seen.insert((Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize)) {
+                    continue;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                pending.push((x.cdr.clone(), y.cdr.clone()));
+                pending.push((x.car.clone(), y.car.clone()));
+            }
+            (Value::Vector(x), Value::Vector(y)) => {
+                if !seen.insert((Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize)) {
+                    continue;
+                }
+                let (x, y) = (x.borrow(), y.borrow());
+                if x.len() != y.len() {
+                    return false;
+                }
+                pending.extend(x.iter().cloned().zip(y.iter().cloned()).rev());
+            }
+            (Value::Bytevector(x), Value::Bytevector(y)) if *x.borrow() == *y.borrow() => (),
+            _ if !eqv(&a, &b) => return false,
+            _ => (),
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn eq_compares_identity() {
+        assert_eq!(
+            run("(define a (list 1)) (define b a) (list (eq? a b) (eq? (list 1) (list 1)) (eq? (pmap 'a 1) (pmap 'a 1)))"),
+            Ok("(#t #f #f)".into())
+        );
+        assert_eq!(
+            run("(define f (lambda (x) x)) (list (eq? f f) (eq? car car) (eq? \"a\" \"a\") (eq? '() '()))"),
+            Ok("(#t #t #t #t)".into())
+        );
+    }
+
+    #[test]
+    fn eqv_compares_persistent_contents() {
+        assert_eq!(
+            run("(list (eqv? (pmap 'a 1) (pmap 'a 1)) (eqv? (list 1) (list 1)) (eqv? 2 2) (eqv? 1 1.0))"),
+            Ok("(#t #f #t #f)".into())
+        );
+    }
+
+    #[test]
+    fn equal_compares_contents() {
+        assert_eq!(
+            run("(list (equal? (list 1 #(2)) (list 1 #(2))) (equal? 1 1.0))"),
+            Ok("(#t #f)".into())
+        );
+        let cyclic =
+            "(define c (list 1 2)) (set-cdr! (cdr c) c) (define d (list 1 2)) (set-cdr! (cdr d) d)";
+        assert_eq!(run(&format!("{} (equal? c d)", cyclic)), Ok("#t".into()));
+    }
+}
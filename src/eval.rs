@@ -1,440 +1,608 @@
+use crate::bytevectors;
+use crate::chars;
+use crate::environments::{self, Environment};
+use crate::equivalence;
+use crate::exceptions::{self, Clause, ErrorObject, Exception, Guard};
+use crate::hashtables::{self, HashTable};
+use crate::libraries;
+use crate::lists;
+use crate::loader;
+use crate::numeric;
 use crate::parser::Expression;
-use std::collections::HashMap;
+use crate::persistent::{self, PMap, PVector};
+use crate::ports::{self, Port};
+use crate::printer::{self, Labels, Style};
+use crate::records::{self, Record, RecordProcedure};
+use crate::strings;
+use crate::types;
+use crate::vectors;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// A procedure implemented in Rust, called with its already-evaluated arguments.
+pub type Builtin = fn(&[Value], &mut HashMap<String, Value>) -> Result<Value, Exception>;
+
+/// Finds the builtin bound to `name`, if any. Builtins are only used for names that aren't bound
+/// in the environment, so definitions and parameters can shadow them. Ones that reach files
+/// are missing in a sandbox.
+fn lookup_builtin(name: &str) -> Option<Builtin> {
+    lookup(name)
+        .or_else(|| numeric::lookup(name))
+        .or_else(|| chars::lookup(name))
+        .or_else(|| strings::lookup(name))
+        .or_else(|| vectors::lookup(name))
+        .or_else(|| bytevectors::lookup(name))
+        .or_else(|| hashtables::lookup(name))
+        .or_else(|| equivalence::lookup(name))
+        .or_else(|| lists::lookup(name))
+        .or_else(|| persistent::lookup(name))
+        .or_else(|| types::lookup(name))
+        .or_else(|| printer::lookup(name))
+        .or_else(|| ports::lookup(name))
+        .or_else(|| environments::lookup(name))
+        .or_else(|| loader::lookup(name))
+        .or_else(|| exceptions::lookup(name))
+        .filter(|_| environments::visible(name))
+}
+
+/// The builtins that call procedures the way the evaluator does.
+fn lookup(name: &str) -> Option<Builtin> {
+    match name {
+        "apply" => Some(|args, env| {
+            let (f, args) = spread(args)?;
+            call(f, args, env)
+        }),
+        _ => None,
+    }
+}
+
+/// The procedure and arguments of a call to `apply`, whose last argument is a list of the
+/// arguments after those given directly.
+fn spread(args: &[Value]) -> Result<(Value, Vec<Value>), Exception> {
+    match args {
+        [f, leading @ .., list] => {
+            let mut all = leading.to_vec();
+            all.extend(list_to_vec(list).ok_or_else(|| {
+                format!(
+                    "Expected a list as the last argument to `apply`, got {:#?}",
+                    list
+                )
+            })?);
+            Ok((f.clone(), all))
+        }
+        _ => Err(format!(
+            "Expected a procedure and a list to `apply`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum Value {
-    // TODO: add more types. Vector? Char? Symbol?
     Number(f64),
-    Function(Vec<String>, Expression),
+    /// The body is shared between copies, giving the procedure an identity.
+    Function(Vec<String>, Rc<Expression>),
+    Builtin(String),
     Bool(bool),
     Integer(isize),
-    Pair(Box<Value>, Box<Value>),
+    Complex(f64, f64),
+    Pair(Rc<RefCell<Pair>>),
     Char(char),
     String(String),
+    Symbol(String),
+    Vector(Rc<RefCell<Vec<Value>>>),
+    Bytevector(Rc<RefCell<Vec<u8>>>),
+    HashTable(Rc<RefCell<HashTable>>),
+    PMap(PMap),
+    PSet(PMap),
+    PVector(PVector),
+    Record(Rc<Record>),
+    RecordProcedure(Rc<RecordProcedure>),
+    Port(Rc<RefCell<Port>>),
+    /// What reading gives at the end of the input
+    Eof,
+    Environment(Environment),
+    Error(Rc<ErrorObject>),
     Nil,
 }
 
-impl std::fmt::Display for Value {
+/// A cons cell. Pairs are shared rather than copied, so a change made with `set-car!` or
+/// `set-cdr!` is seen through every reference to the pair.
+pub struct Pair {
+    pub car: Value,
+    pub cdr: Value,
+}
+
+/// How deeply pairs may be nested in debug output before the rest is elided.
+const MAX_DEBUG_DEPTH: usize = 32;
+
+thread_local! {
+    static DEBUG_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+impl std::fmt::Debug for Pair {
+    /// Error messages print their arguments, which may be very long or even circular lists,
+    /// so this gives up past a certain depth.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Number(n) => write!(f, "{}", *n),
-            Value::Function(_, _) => write!(f, "function"),
-            Value::Bool(true) => write!(f, "#t"),
-            Value::Bool(false) => write!(f, "#f"),
-            Value::Integer(n) => write!(f, "{}", *n),
-            Value::Pair(car, cdr) => write!(f, "({} . {})", car, cdr),
-            Value::Char(c) => write!(f, "#\\{}", c),
-            Value::String(s) => write!(f, "{}", s),
-            Value::Nil => write!(f, "()"),
+        let depth = DEBUG_DEPTH.with(|d| d.get());
+        if depth >= MAX_DEBUG_DEPTH {
+            return write!(f, "...");
+        }
+        DEBUG_DEPTH.with(|d| d.set(depth + 1));
+        let res = f
+            .debug_tuple("Pair")
+            .field(&self.car)
+            .field(&self.cdr)
+            .finish();
+        DEBUG_DEPTH.with(|d| d.set(depth));
+        res
+    }
+}
+
+impl Drop for Pair {
+    /// Unlinks the rest of a list iteratively, since dropping a long list one nested cdr at a
+    /// time would overflow the stack.
+    fn drop(&mut self) {
+        let mut next = std::mem::replace(&mut self.cdr, Value::Nil);
+        while let Value::Pair(rc) = next {
+            match Rc::try_unwrap(rc) {
+                Ok(pair) => next = std::mem::replace(&mut pair.borrow_mut().cdr, Value::Nil),
+                Err(_) => break,
+            }
         }
     }
 }
 
+pub fn cons(car: Value, cdr: Value) -> Value {
+    Value::Pair(Rc::new(RefCell::new(Pair { car, cdr })))
+}
+
+/// Values format the way `write` prints them, with labels for any cycles.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", printer::print(self, Style::Write, Labels::Cycles))
+    }
+}
+
 fn value_to_expression(val: Value) -> Expression {
     match val {
         Value::Bool(b) => Expression::Bool(b),
         Value::Number(n) => Expression::Number(n),
         Value::Integer(n) => Expression::Integer(n),
-        Value::Function(p, b) => Expression::Lambda(p, Box::new(b)),
+        Value::Complex(re, im) => Expression::Complex(re, im),
+        v @ Value::Function(_, _) => Expression::Literal(Box::new(v)),
+        v @ Value::Builtin(_) => Expression::Literal(Box::new(v)),
         Value::Char(c) => Expression::Char(c),
         Value::String(s) => Expression::String(s),
+        v @ Value::Symbol(_) => Expression::Literal(Box::new(v)),
+        v @ Value::Pair(_) => Expression::Literal(Box::new(v)),
+        v @ Value::Vector(_) => Expression::Literal(Box::new(v)),
+        v @ Value::Bytevector(_) => Expression::Literal(Box::new(v)),
+        v @ Value::HashTable(_) => Expression::Literal(Box::new(v)),
+        v @ (Value::PMap(_) | Value::PSet(_) | Value::PVector(_)) => {
+            Expression::Literal(Box::new(v))
+        }
+        v @ (Value::Record(_) | Value::RecordProcedure(_)) => Expression::Literal(Box::new(v)),
+        v @ (Value::Port(_) | Value::Eof | Value::Environment(_) | Value::Error(_)) => {
+            Expression::Literal(Box::new(v))
+        }
         Value::Nil => Expression::Nil,
-        Value::Pair(car, cdr) => Expression::Pair(
-            Box::new(value_to_expression(*car)),
-            Box::new(value_to_expression(*cdr)),
-        ),
     }
 }
 
-fn check_environment(expr: Expression, env: &HashMap<String, Value>) -> Option<Expression> {
+/// Replaces the variables of `expr` bound in `env` or at the top level by their values, except
+/// for those in `shadowed`, which are bound by the procedure itself.
+fn check_environment(
+    expr: Expression,
+    env: &HashMap<String, Value>,
+    shadowed: &HashSet<String>,
+) -> Option<Expression> {
     match expr {
-        Expression::Identifier(s) => match env.get(&s) {
-            Some(v) => Some(value_to_expression(v.clone())),
-            None => Some(Expression::Identifier(s.clone())),
+        Expression::Identifier(s) if shadowed.contains(&s) => Some(Expression::Identifier(s)),
+        Expression::Identifier(s) => match lookup_variable(&s, env) {
+            Some(v) => Some(value_to_expression(v)),
+            None => Some(Expression::Identifier(s)),
         },
-        Expression::Lambda(params, body) => Some(Expression::Lambda(
-            params.to_vec(),
-            Box::new(check_environment(*body, env)?),
-        )),
+        Expression::Lambda(params, body) => {
+            let mut inner = shadowed.clone();
+            inner.extend(params.iter().cloned());
+            Some(Expression::Lambda(
+                params,
+                Box::new(check_environment(*body, env, &inner)?),
+            ))
+        }
         Expression::SExpression(head, tail) => Some(Expression::SExpression(
-            Box::new(check_environment(*head, env)?),
+            Box::new(check_environment(*head, env, shadowed)?),
             tail.iter()
-                .map(|e| check_environment(e.clone(), env).unwrap()) // TODO: better error handling here
+                .map(|e| check_environment(e.clone(), env, shadowed).unwrap()) // TODO: better error handling here
                 .collect(),
         )),
+        Expression::If(cond, if_branch, else_branch) => Some(Expression::If(
+            Box::new(check_environment(*cond, env, shadowed)?),
+            Box::new(check_environment(*if_branch, env, shadowed)?),
+            Box::new(check_environment(*else_branch, env, shadowed)?),
+        )),
+        Expression::Guard(guard) => {
+            // The clauses see the raised object rather than anything captured under its name
+            let mut inner = shadowed.clone();
+            inner.insert(guard.var.clone());
+            let clauses = guard
+                .clauses
+                .into_iter()
+                .map(|clause| match clause {
+                    Clause::Test(test, body) => Some(Clause::Test(
+                        check_environment(test, env, &inner)?,
+                        body.into_iter()
+                            .map(|e| check_environment(e, env, &inner))
+                            .collect::<Option<Vec<Expression>>>()?,
+                    )),
+                    Clause::Receiver(test, receiver) => Some(Clause::Receiver(
+                        check_environment(test, env, &inner)?,
+                        check_environment(receiver, env, &inner)?,
+                    )),
+                })
+                .collect::<Option<Vec<Clause>>>()?;
+            Some(Expression::Guard(Box::new(Guard {
+                var: guard.var,
+                clauses,
+                body: Box::new(check_environment(*guard.body, env, shadowed)?),
+            })))
+        }
+        Expression::Sequence(exprs) => Some(Expression::Sequence(
+            exprs
+                .into_iter()
+                .map(|e| check_environment(e, env, shadowed))
+                .collect::<Option<Vec<Expression>>>()?,
+        )),
         e => Some(e),
     }
 }
 
-// TODO: optimizations? blowing up the stack is way too common: add loops or tail-call optimization; somehow
+/// The value of the variable `name`: the innermost binding in `env`, or else the one at the top
+/// level of the running code.
+pub fn lookup_variable(name: &str, env: &HashMap<String, Value>) -> Option<Value> {
+    env.get(name)
+        .cloned()
+        .or_else(|| environments::global(name))
+}
+
+/// The value of the identifier `name`: a variable, or else a builtin.
+pub fn lookup_identifier(name: &str, env: &HashMap<String, Value>) -> Option<Value> {
+    lookup_variable(name, env)
+        .or_else(|| lookup_builtin(name).map(|_| Value::Builtin(name.to_string())))
+}
+
+/// What an expression in tail position evaluates to: a value, or a call that is left to the
+/// caller so that it doesn't grow the stack.
+enum Tail {
+    Value(Value),
+    Call(Value, Vec<Value>),
+}
+
+/// Evaluates `expr` without making the procedure call it ends with, if any.
+fn eval_tail(expr: &Expression, env: &mut HashMap<String, Value>) -> Result<Tail, Exception> {
+    match expr {
+        Expression::If(cond, if_branch, else_branch) => match eval_expression(cond, env)? {
+            Value::Bool(true) => eval_tail(if_branch, env),
+            Value::Bool(false) => eval_tail(else_branch, env),
+            cond => Err(format!("Expected number in condition, got {:#?}", cond).into()),
+        },
+        Expression::Sequence(exprs) => match exprs.split_last() {
+            Some((last, exprs)) => {
+                for expr in exprs {
+                    eval_expression(expr, env)?;
+                }
+                eval_tail(last, env)
+            }
+            None => Ok(Tail::Value(Value::Nil)),
+        },
+        Expression::SExpression(head, tail) => {
+            let args = tail
+                .iter()
+                .map(|v| eval_expression(v, env))
+                .collect::<Result<Vec<Value>, Exception>>()?;
+            let f = match &**head {
+                Expression::Identifier(s) => match lookup_variable(s, env) {
+                    Some(f) if types::is_procedure(&f) => f,
+                    Some(_) => return Err(format!("{} is not a known function!", s).into()),
+                    None if lookup_builtin(s).is_some() => Value::Builtin(s.clone()),
+                    None => return Err(format!("Symbol {} not found!", s).into()),
+                },
+                _ => eval_expression(head, env)?,
+            };
+            Ok(Tail::Call(f, args))
+        }
+        _ => eval_expression(expr, env).map(Tail::Value),
+    }
+}
+
+/// Calls `f` with `args`. The call that a procedure's body ends with, including one through
+/// `apply`, is made in the same loop, so that tail calls run in constant stack space.
+fn call(
+    mut f: Value,
+    mut args: Vec<Value>,
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    // The bindings of the procedure making the tail call, once there is one
+    let mut caller: Option<HashMap<String, Value>> = None;
+    loop {
+        match f {
+            Value::Builtin(name) if name == "apply" => {
+                (f, args) = spread(&args)?;
+            }
+            Value::Function(params, body) => {
+                if args.len() != params.len() {
+                    return Err(
+                        format!("Expected {} arguments, got {:#?}", params.len(), args).into(),
+                    );
+                }
+                let mut map = caller.take().unwrap_or_else(|| env.clone());
+                for (param, arg) in params.iter().zip(args) {
+                    map.insert(param.clone(), arg);
+                }
+                match eval_tail(&body, &mut map)? {
+                    Tail::Value(v) => return Ok(v),
+                    Tail::Call(g, rest) => (f, args) = (g, rest),
+                }
+                caller = Some(map);
+            }
+            f => return apply_procedure(&f, &args, caller.as_mut().unwrap_or(env)),
+        }
+    }
+}
+
+/// Evaluates `expr`, raising any error it fails with to the handlers right away.
 pub fn eval_expression(
     expr: &Expression,
     env: &mut HashMap<String, Value>,
-) -> Result<Value, String> {
+) -> Result<Value, Exception> {
+    let res = evaluate(expr, env);
+    exceptions::raised(res, env)
+}
+
+fn evaluate(expr: &Expression, env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
     match expr {
         Expression::Number(n) => Ok(Value::Number(*n)),
         Expression::Integer(n) => Ok(Value::Integer(*n)),
+        Expression::Complex(re, im) => Ok(Value::Complex(*re, *im)),
         Expression::Bool(b) => Ok(Value::Bool(*b)),
         Expression::Char(c) => Ok(Value::Char(*c)),
         Expression::String(s) => Ok(Value::String(s.clone())),
-        Expression::Identifier(s) => env
-            .get(s)
-            .cloned()
-            .ok_or(format!("Variable {} not in environment!", s)),
+        Expression::Identifier(s) => lookup_identifier(s, env)
+            .ok_or_else(|| format!("Variable {} not in environment!", s).into()),
         Expression::Nil => Ok(Value::Nil),
         Expression::Lambda(params, body) => {
-            let mut env2 = env.clone();
-            for param in params {
-                env2.remove(param);
-            }
+            let shadowed = params.iter().cloned().collect();
             Ok(Value::Function(
                 params.clone(),
-                check_environment(*body.clone(), &env2).ok_or("Check environment failed!")?,
+                Rc::new(
+                    check_environment(*body.clone(), env, &shadowed)
+                        .ok_or("Check environment failed!")?,
+                ),
             ))
         }
-        Expression::Pair(a, b) => Ok(Value::Pair(
-            Box::new(eval_expression(a, env)?),
-            Box::new(eval_expression(b, env)?),
-        )),
+        Expression::Literal(v) => Ok((**v).clone()),
+        Expression::Guard(guard) => exceptions::guard(guard, env),
+        Expression::DefineLibrary(spec) => {
+            libraries::define_library(spec);
+            Ok(Value::Nil)
+        }
+        Expression::Import(sets) => {
+            libraries::import(sets, env)?;
+            Ok(Value::Nil)
+        }
+        Expression::DefineRecordType(spec) => {
+            records::define_record_type(spec, env)?;
+            Ok(Value::Nil)
+        }
         Expression::Define(s, expr) => {
             let res = eval_expression(expr, env)?;
             env.insert(s.clone(), res);
             Ok(Value::Nil)
         }
-        Expression::If(cond, if_branch, else_branch) => {
-            let cond = eval_expression(&**cond, env)?; // TODO: is there any way to clean the &**? That seems like a code smell
-            match cond {
-                Value::Bool(b) => {
-                    if b {
-                        eval_expression(&**if_branch, env)
-                    } else {
-                        eval_expression(&**else_branch, env)
-                    }
-                }
-                _ => Err(format!("Expected number in condition, got {:#?}", cond)),
+        Expression::If(..) | Expression::Sequence(_) | Expression::SExpression(..) => {
+            match eval_tail(expr, env)? {
+                Tail::Value(v) => Ok(v),
+                Tail::Call(f, args) => call(f, args, env),
             }
         }
-        Expression::SExpression(head, tail) => {
-            let args = tail
-                .iter()
-                .map(|v| eval_expression(v, env))
-                .collect::<Result<Vec<Value>, String>>()?;
-            match &**head {
-                Expression::Identifier(s) => match s.as_str() {
-                    "+" => args
-                        .iter()
-                        .fold(Ok(Value::Integer(0)), |acc, x| match (x, acc) {
-                            (Value::Integer(n), Ok(Value::Integer(n2))) => {
-                                Ok(Value::Integer(*n + n2))
-                            }
-                            (Value::Integer(n), Ok(Value::Number(n2))) => {
-                                Ok(Value::Number(*n as f64 + n2))
-                            }
-                            (Value::Number(n), Ok(Value::Integer(n2))) => {
-                                Ok(Value::Number(*n + n2 as f64))
-                            }
-                            (Value::Number(n), Ok(Value::Number(n2))) => Ok(Value::Number(*n + n2)),
-                            (v, _) => Err(format!("{:#?} is not a number!", v)),
-                        }),
-                    "*" => args
-                        .iter()
-                        .fold(Ok(Value::Integer(1)), |acc, x| match (x, acc) {
-                            (Value::Integer(n), Ok(Value::Integer(n2))) => {
-                                Ok(Value::Integer(*n * n2))
-                            }
-                            (Value::Integer(n), Ok(Value::Number(n2))) => {
-                                Ok(Value::Number(*n as f64 * n2))
-                            }
-                            (Value::Number(n), Ok(Value::Integer(n2))) => {
-                                Ok(Value::Number(*n * n2 as f64))
-                            }
-                            (Value::Number(n), Ok(Value::Number(n2))) => Ok(Value::Number(*n * n2)),
-                            (v, _) => Err(format!("{:#?} is not a number!", v)),
-                        }),
-                    "int" => Ok(Value::Bool(args.iter().all(|x| match x {
-                        Value::Number(n) => (*n - n.floor()).abs() < std::f64::EPSILON,
-                        Value::Integer(_) => true,
-                        _ => false,
-                    }))),
-                    "-" => {
-                        if args.len() == 1 {
-                            match args[0] {
-                                Value::Number(n) => Ok(Value::Number(-n)),
-                                Value::Integer(n) => Ok(Value::Integer(-n)),
-                                _ => Err(format!("{:#?} is not a number!", args[0])),
-                            }
-                        } else {
-                            assert!(matches!(args[0], Value::Number(_) | Value::Integer(_)));
-                            args.iter()
-                                .skip(1)
-                                .fold(Ok(args[0].clone()), |acc, x| match (x, acc) {
-                                    (Value::Integer(n), Ok(Value::Integer(n2))) => {
-                                        Ok(Value::Integer(n2 - *n))
-                                    }
-                                    (Value::Integer(n), Ok(Value::Number(n2))) => {
-                                        Ok(Value::Number(n2 - *n as f64))
-                                    }
-                                    (Value::Number(n), Ok(Value::Integer(n2))) => {
-                                        Ok(Value::Number(n2 as f64 - *n))
-                                    }
-                                    (Value::Number(n), Ok(Value::Number(n2))) => {
-                                        Ok(Value::Number(n2 - *n))
-                                    }
-                                    (v, _) => Err(format!("{:#?} is not a number!", v)),
-                                })
-                        }
-                    }
-                    "/" => {
-                        if args.len() == 1 {
-                            match args[0] {
-                                Value::Number(n) => Ok(Value::Number(1.0 / n)),
-                                Value::Integer(n) => Ok(Value::Number(1.0 / n as f64)),
-                                _ => Err(format!("{:#?} is not a number!", args[0])),
-                            }
-                        } else {
-                            assert!(matches!(args[0], Value::Number(_) | Value::Integer(_)));
-                            args.iter()
-                                .skip(1)
-                                .fold(Ok(args[0].clone()), |acc, x| match (x, acc) {
-                                    (Value::Integer(n), Ok(Value::Integer(n2))) => {
-                                        Ok(Value::Number(n2 as f64 / *n as f64))
-                                    }
-                                    (Value::Integer(n), Ok(Value::Number(n2))) => {
-                                        Ok(Value::Number(n2 / *n as f64))
-                                    }
-                                    (Value::Number(n), Ok(Value::Integer(n2))) => {
-                                        Ok(Value::Number(n2 as f64 / *n))
-                                    }
-                                    (Value::Number(n), Ok(Value::Number(n2))) => {
-                                        Ok(Value::Number(n2 / *n))
-                                    }
-                                    (v, _) => Err(format!("{:#?} is not a number!", v)),
-                                })
-                        }
-                    }
-                    "<" => args
-                        .iter()
-                        .skip(1)
-                        .fold(Ok(args[0].clone()), |acc, x| match (acc, x) {
-                            (Ok(Value::Number(n2)), Value::Number(n)) => {
-                                if n2 == 0.0 {
-                                    Ok(Value::Number(0.0))
-                                } else if n2 < *n {
-                                    Ok(Value::Number(*n))
-                                } else {
-                                    Ok(Value::Number(0.0))
-                                }
-                            }
-                            (Ok(Value::Integer(n2)), Value::Number(n)) => {
-                                if n2 == 0 {
-                                    Ok(Value::Number(0.0))
-                                } else if (n2 as f64) < *n {
-                                    Ok(Value::Number(*n))
-                                } else {
-                                    Ok(Value::Number(0.0))
-                                }
-                            }
-                            (Ok(Value::Number(n2)), Value::Integer(n)) => {
-                                if n2 == 0.0 {
-                                    Ok(Value::Number(0.0))
-                                } else if n2 < *n as f64 {
-                                    Ok(Value::Number(*n as f64))
-                                } else {
-                                    Ok(Value::Number(0.0))
-                                }
-                            }
-                            (Ok(Value::Integer(n2)), Value::Integer(n)) => {
-                                if n2 == 0 {
-                                    Ok(Value::Integer(0))
-                                } else if n2 < *n {
-                                    Ok(Value::Integer(*n))
-                                } else {
-                                    Ok(Value::Integer(0))
-                                }
-                            }
-                            _ => Err(format!("{:#?} not a number!", x)),
-                        })
-                        .map(|val| match val {
-                            Value::Number(n) => {
-                                if n == 0.0 {
-                                    Value::Bool(false)
-                                } else {
-                                    Value::Bool(true)
-                                }
-                            }
-                            Value::Integer(0) => Value::Bool(false),
-                            Value::Integer(_) => Value::Bool(true),
-                            _ => unreachable!(),
-                        }),
-                    "=" => Ok(Value::Bool(args.iter().skip(1).all(|v| {
-                        match (v, args[0].clone()) {
-                            (Value::Number(n), Value::Number(n2)) => *n == n2,
-                            (Value::Number(n), Value::Integer(n2)) => *n == n2 as f64,
-                            (Value::Integer(n), Value::Number(n2)) => *n as f64 == n2,
-                            (Value::Integer(n), Value::Integer(n2)) => *n == n2,
-                            _ => false,
-                        }
-                    }))),
-                    "not" => {
-                        if args.len() != 1 {
-                            Err(format!("Expected 1 arg, got {:#?}", args))
-                        } else {
-                            Ok(Value::Bool(match args[0] {
-                                Value::Bool(b) => !b,
-                                _ => false,
-                            }))
-                        }
-                    }
-                    "display" => {
-                        if args.len() == 1 {
-                            println!("{}", args[0]);
-                            Ok(Value::Nil)
-                        } else {
-                            Err(format!(
-                                "Expected one argument to `display`, got {:#?}",
-                                args
-                            ))
-                        }
-                    }
-                    "cons" => {
-                        if args.len() != 2 {
-                            Err(format!("Expected two arguments to `cons`, got {:#?}", args))
-                        } else {
-                            Ok(Value::Pair(
-                                Box::new(args[0].clone()),
-                                Box::new(args[1].clone()),
-                            ))
-                        }
-                    }
-                    "car" => {
-                        if args.len() != 1 {
-                            Err(format!("Expected one argument to `car`, got {:#?}", args))
-                        } else {
-                            match args[0].clone() {
-                                Value::Pair(a, _) => Ok(*a),
-                                _ => Err(format!("{:#?} not a pair!", args[0])),
-                            }
-                        }
-                    }
-                    "cdr" => {
-                        if args.len() != 1 {
-                            Err(format!("Expected one argument to `cdr`, got {:#?}", args))
-                        } else {
-                            match &args[0] {
-                                Value::Pair(_, b) => Ok(*b.clone()),
-                                _ => Err(format!("{:#?} not a pair!", args[0])),
-                            }
-                        }
-                    }
-                    "list" => Ok(args.iter().rfold(Value::Nil, |acc, x| {
-                        Value::Pair(Box::new(x.clone()), Box::new(acc))
-                    })),
-                    "null?" => {
-                        if args.len() != 1 {
-                            Err(format!("Expected one argument to `null?`, got {:#?}", args))
-                        } else {
-                            Ok(match &args[0] {
-                                Value::Nil => Value::Bool(true),
-                                _ => Value::Bool(false),
-                            })
-                        }
-                    }
-                    "string->list" => {
-                        if args.len() != 1 {
-                            Err(format!(
-                                "Expected one argument to `string->list`, got {:#?}",
-                                args
-                            ))
-                        } else {
-                            match args[0].clone() {
-                                Value::String(s) => Ok(s.chars().rfold(Value::Nil, |acc, x| {
-                                    Value::Pair(Box::new(Value::Char(x)), Box::new(acc))
-                                })),
-                                v => Err(format!(
-                                    "Expected string argument to `string->list`, got {:#?}",
-                                    v
-                                )),
-                            }
-                        }
-                    }
-                    "list->string" => {
-                        if args.len() != 1 {
-                            Err(format!(
-                                "Expected one argument to `list->string`, got {:#?}",
-                                args
-                            ))
-                        } else {
-                            match args[0].clone() {
-                                Value::Pair(car, cdr) => {
-                                    let mut res = String::new();
-                                    let mut car = car;
-                                    let mut cdr = cdr;
-                                    loop {
-                                        if let Value::Char(c) = *car {
-                                            res.push(c);
-                                        } else {
-                                            return Err(format!(
-                                                "Expected list of chars, got {:#?}",
-                                                args[0]
-                                            ));
-                                        }
-                                        if let Value::Pair(car2, cdr2) = *cdr {
-                                            car = car2;
-                                            cdr = cdr2;
-                                        } else {
-                                            break;
-                                        }
-                                    }
-                                    Ok(Value::String(res))
-                                }
-                                v => Err(format!(
-                                    "Expected list argument to `list->string`, got {:#?}",
-                                    v
-                                )),
-                            }
-                        }
-                    }
-                    "newline" => {
-                        println!();
-                        Ok(Value::Nil)
-                    }
-                    s => {
-                        let f = env.get(s).ok_or(format!("Symbol {} not found!", s))?;
-                        if let Value::Function(params, body) = f {
-                            let mut map = env.clone();
-                            assert!(args.len() <= params.len());
-                            for (i, arg) in args.iter().enumerate() {
-                                map.insert(params[i].clone(), arg.clone());
-                            }
-                            eval_expression(&body, &mut map)
-                        } else {
-                            Err(format!("{} is not a known function!", s))
-                        }
-                    }
-                },
-                _ => {
-                    let res = eval_expression(&**head, env)?;
-                    match res {
-                        Value::Function(params, body) => {
-                            let mut map = env.clone();
-                            assert!(args.len() <= params.len());
-                            for (i, arg) in args.iter().enumerate() {
-                                map.insert(params[i].clone(), arg.clone());
-                            }
-                            eval_expression(&body, &mut map)
-                        }
-                        Value::Number(n) => Err(format!("{} is a number, not a function!", n)),
-                        Value::Nil => Err(format!("Nil is not callable!")),
-                        Value::Bool(b) => Err(format!("{} is a boolean, not a function!", b)),
-                        Value::Char(c) => Err(format!("{} is a char, not a function!", c)),
-                        Value::Integer(n) => Err(format!("{} is an integer, not a function!", n)),
-                        Value::Pair(_, _) => Err(format!("Expected a function, got a pair!")),
-                        Value::String(s) => {
-                            Err(format!("Expected a function, got the string {}", s))
-                        }
-                    }
+    }
+}
+
+/// Calls a user-defined or builtin procedure with already-evaluated arguments. If it fails, the
+/// error is raised to the handlers from here.
+pub fn apply_procedure(
+    f: &Value,
+    args: &[Value],
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    let res = match f {
+        Value::Function(..) => call(f.clone(), args.to_vec(), env),
+        Value::Builtin(name) => match lookup_builtin(name) {
+            Some(builtin) => builtin(args, env),
+            None => Err(format!("Unknown builtin {}!", name).into()),
+        },
+        Value::RecordProcedure(p) => records::apply(p, args),
+        Value::Number(n) => Err(format!("{} is a number, not a function!", n).into()),
+        Value::Nil => Err("Nil is not callable!".into()),
+        Value::Bool(b) => Err(format!("{} is a boolean, not a function!", b).into()),
+        Value::Char(c) => Err(format!("{} is a char, not a function!", c).into()),
+        Value::Integer(n) => Err(format!("{} is an integer, not a function!", n).into()),
+        Value::Complex(re, im) => Err(format!(
+            "{} is a complex number, not a function!",
+            numeric::format_complex(*re, *im)
+        )
+        .into()),
+        Value::Pair(_) => Err("Expected a function, got a pair!".into()),
+        Value::String(s) => Err(format!("Expected a function, got the string {}", s).into()),
+        Value::Symbol(s) => Err(format!("Expected a function, got the symbol {}", s).into()),
+        Value::Vector(_) => Err("Expected a function, got a vector!".into()),
+        Value::Bytevector(_) => Err("Expected a function, got a bytevector!".into()),
+        Value::HashTable(_) => Err("Expected a function, got a hash table!".into()),
+        Value::PMap(_) => Err("Expected a function, got a pmap!".into()),
+        Value::PSet(_) => Err("Expected a function, got a pset!".into()),
+        Value::PVector(_) => Err("Expected a function, got a pvector!".into()),
+        Value::Port(_) => Err("Expected a function, got a port!".into()),
+        Value::Eof => Err("Expected a function, got the eof object!".into()),
+        Value::Environment(_) => Err("Expected a function, got an environment!".into()),
+        Value::Error(_) => Err("Expected a function, got an error object!".into()),
+        Value::Record(r) => {
+            Err(format!("Expected a function, got a {} record!", r.record_type.name).into())
+        }
+    };
+    exceptions::raised(res, env)
+}
+
+/// Reads the index `v` into a string, vector or the like of length `len`, for the builtin
+/// `name`. An index equal to the length is only allowed at the `end` of a range, or where
+/// elements can be added.
+pub fn index_arg(name: &str, v: &Value, len: usize, end: bool) -> Result<usize, Exception> {
+    match v {
+        Value::Integer(n) if *n >= 0 && ((*n as usize) < len || (end && *n as usize == len)) => {
+            Ok(*n as usize)
+        }
+        Value::Integer(n) => Err(out_of_range(name, *n, len)),
+        v => Err(format!("Expected integer index to `{}`, got {:#?}", name, v).into()),
+    }
+}
+
+/// Reads optional `start` and `end` arguments delimiting a range of something of length `len`.
+pub fn range_args(name: &str, args: &[Value], len: usize) -> Result<(usize, usize), Exception> {
+    let end = match args.get(1) {
+        Some(end) => index_arg(name, end, len, true)?,
+        None => len,
+    };
+    let start = match args.first() {
+        Some(start) => index_arg(name, start, end, true)?,
+        None => 0,
+    };
+    Ok((start, end))
+}
+
+/// The error for an index past the end of something of length `len`.
+pub fn out_of_range(name: &str, index: isize, len: usize) -> Exception {
+    format!(
+        "Index {} out of range for `{}` with length {}",
+        index, name, len
+    )
+    .into()
+}
+
+/// Collects the elements of a proper list, or `None` if `list` isn't one (including if it is
+/// circular).
+pub fn list_to_vec(list: &Value) -> Option<Vec<Value>> {
+    let mut res = Vec::new();
+    let mut current = list.clone();
+    // Moves along at half speed, so a circular list eventually brings `current` back to it
+    let mut slow = list.clone();
+    loop {
+        let next = match &current {
+            Value::Nil => return Some(res),
+            Value::Pair(p) => {
+                let p = p.borrow();
+                res.push(p.car.clone());
+                p.cdr.clone()
+            }
+            _ => return None,
+        };
+        current = next;
+        if res.len() % 2 == 0 {
+            slow = match &slow {
+                Value::Pair(p) => p.borrow().cdr.clone(),
+                v => v.clone(),
+            };
+            if let (Value::Pair(a), Value::Pair(b)) = (&current, &slow) {
+                if Rc::ptr_eq(a, b) {
+                    return None;
                 }
             }
         }
     }
 }
+
+pub fn vec_to_list(values: Vec<Value>) -> Value {
+    values.into_iter().rfold(Value::Nil, |acc, x| cons(x, acc))
+}
+
+/// Evaluates each form of `source` after the prelude, giving the last value the way `write`
+/// prints it, or the message of what was raised.
+#[cfg(test)]
+pub fn run(source: &str) -> Result<String, String> {
+    let prelude = crate::prelude::environment().map_err(|e| e.to_string())?;
+    *environments::program().borrow_mut() = prelude;
+    let mut res = Value::Nil;
+    for expr in crate::parser::parse_all(source).map_err(|e| e.to_string())? {
+        res = environments::eval_top_level(&expr).map_err(|e| e.to_string())?;
+    }
+    Ok(res.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{index_arg, range_args, run, Value};
+
+    #[test]
+    fn indices_are_checked_against_the_length() {
+        assert_eq!(index_arg("f", &Value::Integer(2), 3, false).ok(), Some(2));
+        assert!(index_arg("f", &Value::Integer(3), 3, false).is_err());
+        assert_eq!(index_arg("f", &Value::Integer(3), 3, true).ok(), Some(3));
+        assert!(index_arg("f", &Value::Integer(-1), 3, true).is_err());
+        assert!(index_arg("f", &Value::Number(1.0), 3, true).is_err());
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_stack_space() {
+        let looping = "(define loop (lambda (n) (if (= n 0) 'done (loop (- n 1)))))";
+        assert_eq!(
+            run(&format!("{} (loop 100000)", looping)),
+            Ok("done".into())
+        );
+        let applying = "(define loop (lambda (n) (if (= n 0) 'done (apply loop (list (- n 1))))))";
+        assert_eq!(
+            run(&format!("{} (loop 100000)", applying)),
+            Ok("done".into())
+        );
+        let mutual = "(define ev? (lambda (n) (if (= n 0) #t (od? (- n 1))))) \
+                      (define od? (lambda (n) (if (= n 0) #f (ev? (- n 1)))))";
+        assert_eq!(run(&format!("{} (ev? 100001)", mutual)), Ok("#f".into()));
+    }
+
+    #[test]
+    fn apply_spreads_its_last_argument() {
+        assert_eq!(run("(apply list 1 2 '(3 4))"), Ok("(1 2 3 4)".into()));
+        assert_eq!(run("(apply apply list '(1 (2)))"), Ok("(1 2)".into()));
+        assert_eq!(
+            run("(map apply (list + *) '((1 2) (3 4)))"),
+            Ok("(3 12)".into())
+        );
+        assert!(run("(apply + 1)").is_err());
+        assert!(run("(apply (lambda (x) x) '(1 2))").is_err());
+    }
+
+    #[test]
+    fn ranges_default_to_the_whole_length() {
+        assert_eq!(range_args("f", &[], 4).ok(), Some((0, 4)));
+        assert_eq!(range_args("f", &[Value::Integer(1)], 4).ok(), Some((1, 4)));
+        let range = [Value::Integer(1), Value::Integer(3)];
+        assert_eq!(range_args("f", &range, 4).ok(), Some((1, 3)));
+        assert!(range_args("f", &[Value::Integer(3), Value::Integer(2)], 4).is_err());
+        assert!(range_args("f", &[Value::Integer(0), Value::Integer(5)], 4).is_err());
+    }
+
+    #[test]
+    fn sequences_report_the_same_errors() {
+        for expr in [
+            "(string-ref \"abc\" 3)",
+            "(vector-ref (vector 1 2 3) 3)",
+            "(bytevector-u8-ref (bytevector 1 2 3) 3)",
+        ] {
+            let name = expr[1..].split(' ').next().unwrap();
+            assert_eq!(
+                run(expr),
+                Err(format!("Index 3 out of range for `{}` with length 3", name))
+            );
+        }
+    }
+}
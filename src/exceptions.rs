@@ -0,0 +1,366 @@
+use crate::eval::{apply_procedure, eval_expression, list_to_vec, vec_to_list, Builtin, Value};
+use crate::parser::{datum_to_expression, Expression};
+use crate::printer::{self, Labels, Style};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "error" => |args, env| match args {
+            [message, irritants @ ..] => {
+                let message = match message {
+                    Value::String(s) => s.clone(),
+                    v => printer::print(v, Style::Write, Labels::Cycles),
+                };
+                Err(raise(error_object(message, irritants.to_vec()), env))
+            }
+            _ => Err(format!("Expected a message to `error`, got {:#?}", args).into()),
+        },
+        "raise" => |args, env| match args {
+            [obj] => Err(raise(obj.clone(), env)),
+            _ => Err(format!("Expected one argument to `raise`, got {:#?}", args).into()),
+        },
+        "raise-continuable" => |args, env| match args {
+            [obj] => raise_continuable(obj, env),
+            _ => Err(format!(
+                "Expected one argument to `raise-continuable`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "with-exception-handler" => |args, env| match args {
+            [handler, thunk] => with_exception_handler(handler, thunk, env),
+            _ => Err(format!(
+                "Expected a handler and a thunk to `with-exception-handler`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "error-object?" => |args, _| match args {
+            [v] => Ok(Value::Bool(matches!(v, Value::Error(_)))),
+            _ => Err(format!("Expected one argument to `error-object?`, got {:#?}", args).into()),
+        },
+        "error-object-message" => |args, _| match args {
+            [Value::Error(e)] => Ok(Value::String(e.message.clone())),
+            _ => Err(format!(
+                "Expected an error object to `error-object-message`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "error-object-irritants" => |args, _| match args {
+            [Value::Error(e)] => Ok(vec_to_list(e.irritants.clone())),
+            _ => Err(format!(
+                "Expected an error object to `error-object-irritants`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// What `error` makes, and what builtins raise when they fail.
+#[derive(Debug)]
+pub struct ErrorObject {
+    pub message: String,
+    pub irritants: Vec<Value>,
+}
+
+fn error_object(message: String, irritants: Vec<Value>) -> Value {
+    Value::Error(Rc::new(ErrorObject { message, irritants }))
+}
+
+/// Why evaluation stopped without a value: the object that was raised, which is an error
+/// object unless it was passed to `raise` by the program.
+#[derive(Clone, Debug)]
+pub struct Exception {
+    obj: Value,
+    /// Whether the handlers have been called with it, so that it is only on its way out to a
+    /// guard or the top level
+    handled: bool,
+}
+
+/// Builtins fail with a message, which becomes an error object without irritants. It is raised
+/// to the handlers where the builtin was called.
+impl From<String> for Exception {
+    fn from(message: String) -> Exception {
+        Exception {
+            obj: error_object(message, Vec::new()),
+            handled: false,
+        }
+    }
+}
+
+impl From<&str> for Exception {
+    fn from(message: &str) -> Exception {
+        Exception::from(message.to_string())
+    }
+}
+
+/// The message printed for an exception no handler caught.
+impl std::fmt::Display for Exception {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.obj {
+            Value::Error(e) => {
+                write!(f, "{}", e.message)?;
+                for irritant in &e.irritants {
+                    write!(f, " {}", irritant)?;
+                }
+                Ok(())
+            }
+            v => write!(f, "Uncaught exception: {}", v),
+        }
+    }
+}
+
+/// Where a raised object goes.
+enum Handler {
+    /// A procedure installed by `with-exception-handler`, which is called with the object
+    Procedure(Value),
+    /// The body of a `guard`, whose clauses get the object once it has left the body
+    Guard,
+}
+
+thread_local! {
+    /// The handlers installed by `with-exception-handler` and `guard`, innermost last.
+    static HANDLERS: RefCell<Vec<Handler>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Calls `thunk` with `handler` installed.
+fn with_exception_handler(
+    handler: &Value,
+    thunk: &Value,
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    HANDLERS.with(|handlers| {
+        handlers
+            .borrow_mut()
+            .push(Handler::Procedure(handler.clone()))
+    });
+    let res = apply_procedure(thunk, &[], env);
+    HANDLERS.with(|handlers| handlers.borrow_mut().pop());
+    res
+}
+
+/// Calls `f` with the innermost handler removed, so that raising from the handler goes to the
+/// next one out.
+fn with_handler<T>(f: impl FnOnce(Option<&Handler>) -> T) -> T {
+    let handler = HANDLERS.with(|handlers| handlers.borrow_mut().pop());
+    let res = f(handler.as_ref());
+    if let Some(handler) = handler {
+        HANDLERS.with(|handlers| handlers.borrow_mut().push(handler));
+    }
+    res
+}
+
+/// Raises `obj` where it is, calling the innermost handler with it. A handler can't return
+/// from `raise`, so if it does, a secondary error is raised to the handlers outside of it. The
+/// exception then leaves for the innermost guard, or the top level.
+pub fn raise(obj: Value, env: &mut HashMap<String, Value>) -> Exception {
+    with_handler(|handler| match handler {
+        Some(Handler::Procedure(handler)) => {
+            match apply_procedure(handler, std::slice::from_ref(&obj), env) {
+                Ok(_) => raise(
+                    error_object("Handler returned from `raise` of".into(), vec![obj]),
+                    env,
+                ),
+                Err(e) => e,
+            }
+        }
+        _ => Exception { obj, handled: true },
+    })
+}
+
+/// Gives `res`, raising its exception first if the handlers haven't been called with it yet,
+/// as for a builtin that failed.
+pub fn raised(
+    res: Result<Value, Exception>,
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    match res {
+        Err(Exception {
+            obj,
+            handled: false,
+        }) => Err(raise(obj, env)),
+        res => res,
+    }
+}
+
+/// Calls the innermost handler with `obj`, and returns what it gives. Inside the body of a
+/// `guard`, `obj` is raised to the guard instead.
+fn raise_continuable(obj: &Value, env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    with_handler(|handler| match handler {
+        Some(Handler::Procedure(handler)) => {
+            apply_procedure(handler, std::slice::from_ref(obj), env)
+        }
+        _ => Err(Exception {
+            obj: obj.clone(),
+            handled: true,
+        }),
+    })
+}
+
+/// A parsed `(guard (var clause ...) body ...)` form.
+#[derive(Clone, Debug)]
+pub struct Guard {
+    pub var: String,
+    pub clauses: Vec<Clause>,
+    pub body: Box<Expression>,
+}
+
+/// One of the clauses of `guard`, tried in order on what was raised.
+#[derive(Clone, Debug)]
+pub enum Clause {
+    /// `(test expr ...)`, giving the value of the test if there are no expressions. `else` is
+    /// a test that always passes.
+    Test(Expression, Vec<Expression>),
+    /// `(test => receiver)`, calling the receiver with the value of the test
+    Receiver(Expression, Expression),
+}
+
+impl Guard {
+    /// Reads the parts of a `guard` form following the keyword, as data.
+    pub fn new(parts: &[Value]) -> Result<Guard, Exception> {
+        let (spec, body) = match parts {
+            [spec, body @ ..] if !body.is_empty() => (spec, body),
+            _ => return Err("Expected (var clause ...) and a body in guard!".into()),
+        };
+        let (var, clauses) = match list_to_vec(spec).as_deref() {
+            Some([Value::Symbol(var), clauses @ ..]) => (var.clone(), clauses.to_vec()),
+            _ => return Err(format!("Expected (var clause ...) in guard, got {}", spec).into()),
+        };
+        let clauses = clauses
+            .iter()
+            .map(|clause| match list_to_vec(clause).as_deref() {
+                Some([Value::Symbol(s), body @ ..]) if s == "else" => Ok(Clause::Test(
+                    Expression::Bool(true),
+                    body.iter()
+                        .map(datum_to_expression)
+                        .collect::<Result<Vec<Expression>, Exception>>()?,
+                )),
+                Some([test, Value::Symbol(s), receiver]) if s == "=>" => Ok(Clause::Receiver(
+                    datum_to_expression(test)?,
+                    datum_to_expression(receiver)?,
+                )),
+                Some([test, body @ ..]) => Ok(Clause::Test(
+                    datum_to_expression(test)?,
+                    body.iter()
+                        .map(datum_to_expression)
+                        .collect::<Result<Vec<Expression>, Exception>>()?,
+                )),
+                _ => Err(format!("Invalid clause {} in guard", clause).into()),
+            })
+            .collect::<Result<Vec<Clause>, Exception>>()?;
+        let body = body
+            .iter()
+            .map(datum_to_expression)
+            .collect::<Result<Vec<Expression>, Exception>>()?;
+        Ok(Guard {
+            var,
+            clauses,
+            body: Box::new(Expression::Sequence(body)),
+        })
+    }
+}
+
+/// Evaluates the body of `guard`, which takes what it raises ahead of the handlers outside of
+/// it. If it raises, the raised object is bound to the guard's variable for the clauses, and
+/// the first clause whose test passes gives the result. With no such clause, the object is
+/// raised again, to the handlers outside of the guard.
+pub fn guard(guard: &Guard, env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    HANDLERS.with(|handlers| handlers.borrow_mut().push(Handler::Guard));
+    let res = eval_expression(&guard.body, env);
+    HANDLERS.with(|handlers| handlers.borrow_mut().pop());
+    let obj = match res {
+        Err(e) => e.obj,
+        res => return res,
+    };
+    let mut scope = env.clone();
+    scope.insert(guard.var.clone(), obj.clone());
+    let passed = |v: &Value| !matches!(v, Value::Bool(false));
+    for clause in &guard.clauses {
+        match clause {
+            Clause::Test(test, body) => {
+                let mut res = eval_expression(test, &mut scope)?;
+                if passed(&res) {
+                    for expr in body {
+                        res = eval_expression(expr, &mut scope)?;
+                    }
+                    return Ok(res);
+                }
+            }
+            Clause::Receiver(test, receiver) => {
+                let v = eval_expression(test, &mut scope)?;
+                if passed(&v) {
+                    let receiver = eval_expression(receiver, &mut scope)?;
+                    return apply_procedure(&receiver, &[v], &mut scope);
+                }
+            }
+        }
+    }
+    Err(raise(obj, env))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn handlers_return_from_raise_continuable() {
+        assert_eq!(
+            run("(with-exception-handler (lambda (e) 10) (lambda () (+ (raise-continuable 'oops) 1)))"),
+            Ok("11".into())
+        );
+        assert_eq!(
+            run("(with-exception-handler (lambda (e) 0) (lambda () (raise 'boom)))"),
+            Err("Handler returned from `raise` of boom".into())
+        );
+    }
+
+    #[test]
+    fn nested_handlers_pass_raises_outwards() {
+        let source = "(define v (make-vector 1 #f))
+            (guard (e (#t (list e (vector-ref v 0))))
+              (with-exception-handler
+                (lambda (e) (vector-set! v 0 e))
+                (lambda ()
+                  (with-exception-handler
+                    (lambda (e) (raise (list 'inner e)))
+                    (lambda () (raise 'x))))))";
+        assert_eq!(
+            run(source),
+            Ok("(#<error \"Handler returned from `raise` of\" (inner x)> (inner x))".into())
+        );
+        assert_eq!(
+            run("(guard (e (#t e)) (with-exception-handler (lambda (e) (raise (error-object-message e))) (lambda () (error \"bad\" 1))))"),
+            Ok("\"bad\"".into())
+        );
+    }
+
+    #[test]
+    fn handlers_run_before_guards_unwind() {
+        let source = "(with-output-to-string (lambda ()
+              (guard (e (#t (display \"guarded\")))
+                (with-exception-handler
+                  (lambda (e) (display \"handled \"))
+                  (lambda () (vector-ref (vector) 0))))))";
+        assert_eq!(run(source), Ok("\"handled guarded\"".into()));
+    }
+
+    #[test]
+    fn guards_come_before_outer_handlers() {
+        assert_eq!(
+            run("(with-exception-handler (lambda (e) 'outer) (lambda () (guard (e (#t 'guarded)) (raise 'x))))"),
+            Ok("guarded".into())
+        );
+        let source = "(define w (make-vector 1 #f))
+            (guard (e (#t (vector-ref w 0)))
+              (with-exception-handler
+                (lambda (e) (vector-set! w 0 (list 'outer e)))
+                (lambda () (guard (e ((string? e) 'no)) (raise 'x)))))";
+        assert_eq!(run(source), Ok("(outer x)".into()));
+    }
+}
@@ -0,0 +1,483 @@
+use crate::environments::Environment;
+use crate::eval::{apply_procedure, cons, vec_to_list, Builtin, Value};
+use crate::exceptions::Exception;
+use crate::types;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "make-hash-table" => make_hash_table,
+        "hash-table?" => |args, _| match args {
+            [v] => Ok(Value::Bool(matches!(v, Value::HashTable(_)))),
+            _ => Err(format!("Expected one argument to `hash-table?`, got {:#?}", args).into()),
+        },
+        "hash-table-set!" => hash_table_set,
+        "hash-table-ref" => hash_table_ref,
+        "hash-table-ref/default" => hash_table_ref_default,
+        "hash-table-delete!" => hash_table_delete,
+        "hash-table-contains?" => hash_table_contains,
+        "hash-table-count" => hash_table_count,
+        "hash-table-keys" => |args, _| {
+            let entries = entries("hash-table-keys", args)?;
+            Ok(vec_to_list(entries.into_iter().map(|(k, _)| k).collect()))
+        },
+        "hash-table-values" => |args, _| {
+            let entries = entries("hash-table-values", args)?;
+            Ok(vec_to_list(entries.into_iter().map(|(_, v)| v).collect()))
+        },
+        "hash-table->alist" => |args, _| {
+            let entries = entries("hash-table->alist", args)?;
+            Ok(vec_to_list(
+                entries.into_iter().map(|(k, v)| cons(k, v)).collect(),
+            ))
+        },
+        "hash-table-update!" => hash_table_update,
+        "hash-table-update!/default" => hash_table_update_default,
+        "hash-table-walk" => hash_table_walk,
+        "hash-table-fold" => hash_table_fold,
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// A hashable stand-in for a `Value`. Atoms and the contents of strings, pairs, vectors and
+/// persistent collections are compared by value, while other mutable objects are compared by
+/// identity. Integers and floats are never the same key, since they differ in exactness.
+/// Persistent maps and sets sort their keys, so that equal collections have equal keys
+/// whatever order they were built in.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashKey {
+    Integer(isize),
+    Number(u64),
+    Complex(u64, u64),
+    Bool(bool),
+    Char(char),
+    String(String),
+    Symbol(String),
+    Builtin(String),
+    Pair(Box<HashKey>, Box<HashKey>),
+    Vector(Vec<HashKey>),
+    Bytevector(Vec<u8>),
+    PMap(Vec<(HashKey, HashKey)>),
+    PSet(Vec<HashKey>),
+    PVector(Vec<HashKey>),
+    Identity(usize),
+    Eof,
+    InteractionEnvironment,
+    Nil,
+}
+
+impl HashKey {
+    /// A key that compares pairs, vectors and bytevectors by identity rather than contents, like `eqv?`.
+    pub fn identity(v: &Value) -> Result<HashKey, Exception> {
+        match v {
+            Value::Pair(p) => Ok(HashKey::Identity(Rc::as_ptr(p) as usize)),
+            Value::Vector(v) => Ok(HashKey::Identity(Rc::as_ptr(v) as usize)),
+            Value::Bytevector(b) => Ok(HashKey::Identity(Rc::as_ptr(b) as usize)),
+            v => HashKey::new(v),
+        }
+    }
+
+    pub fn new(v: &Value) -> Result<HashKey, Exception> {
+        HashKey::build(v, &mut HashSet::new())
+    }
+
+    /// The key of `v`, where `path` holds the pairs and vectors that `v` is inside of. Meeting
+    /// one of those again means the structure is circular, and it can't be made into a key.
+    fn build(v: &Value, path: &mut HashSet<usize>) -> Result<HashKey, Exception> {
+        Ok(match v {
+            Value::Integer(n) => HashKey::Integer(*n),
+            Value::Number(n) => HashKey::Number(n.to_bits()),
+            Value::Complex(re, im) => HashKey::Complex(re.to_bits(), im.to_bits()),
+            Value::Bool(b) => HashKey::Bool(*b),
+            Value::Char(c) => HashKey::Char(*c),
+            Value::String(s) => HashKey::String(s.clone()),
+            Value::Symbol(s) => HashKey::Symbol(s.clone()),
+            Value::Builtin(name) => HashKey::Builtin(name.clone()),
+            Value::Pair(p) => {
+                let id = enter(path, Rc::as_ptr(p) as usize)?;
+                let p = p.borrow();
+                let key = HashKey::Pair(
+                    Box::new(HashKey::build(&p.car, path)?),
+                    Box::new(HashKey::build(&p.cdr, path)?),
+                );
+                path.remove(&id);
+                key
+            }
+            Value::Vector(v) => {
+                let id = enter(path, Rc::as_ptr(v) as usize)?;
+                let key = HashKey::Vector(
+                    v.borrow()
+                        .iter()
+                        .map(|x| HashKey::build(x, path))
+                        .collect::<Result<Vec<HashKey>, Exception>>()?,
+                );
+                path.remove(&id);
+                key
+            }
+            Value::Bytevector(b) => HashKey::Bytevector(b.borrow().clone()),
+            Value::HashTable(t) => HashKey::Identity(Rc::as_ptr(t) as usize),
+            Value::Record(r) => HashKey::Identity(Rc::as_ptr(r) as usize),
+            Value::RecordProcedure(p) => HashKey::Identity(Rc::as_ptr(p) as usize),
+            Value::Port(p) => HashKey::Identity(Rc::as_ptr(p) as usize),
+            Value::Error(e) => HashKey::Identity(Rc::as_ptr(e) as usize),
+            Value::Environment(Environment::Sandbox(b)) => {
+                HashKey::Identity(Rc::as_ptr(b) as usize)
+            }
+            Value::Environment(Environment::Interaction) => HashKey::InteractionEnvironment,
+            Value::PMap(map) => {
+                let mut entries = map
+                    .entries()
+                    .iter()
+                    .map(|(k, v)| Ok((HashKey::build(k, path)?, HashKey::build(v, path)?)))
+                    .collect::<Result<Vec<(HashKey, HashKey)>, Exception>>()?;
+                entries.sort();
+                HashKey::PMap(entries)
+            }
+            Value::PSet(set) => {
+                let mut elements = set
+                    .entries()
+                    .iter()
+                    .map(|(x, _)| HashKey::build(x, path))
+                    .collect::<Result<Vec<HashKey>, Exception>>()?;
+                elements.sort();
+                HashKey::PSet(elements)
+            }
+            Value::PVector(v) => HashKey::PVector(
+                v.elements()
+                    .iter()
+                    .map(|x| HashKey::build(x, path))
+                    .collect::<Result<Vec<HashKey>, Exception>>()?,
+            ),
+            Value::Eof => HashKey::Eof,
+            Value::Nil => HashKey::Nil,
+            Value::Function(_, body) => HashKey::Identity(Rc::as_ptr(body) as usize),
+        })
+    }
+}
+
+/// Adds `id` to the objects on the path to the current one, failing if it is already there.
+fn enter(path: &mut HashSet<usize>, id: usize) -> Result<usize, Exception> {
+    if path.insert(id) {
+        Ok(id)
+    } else {
+        Err("Can't use a circular structure as a key!".into())
+    }
+}
+
+/// A mutable hash table. Entries are kept in a vector so that iteration order is
+/// deterministic: insertion order, until something is deleted.
+#[derive(Debug)]
+pub struct HashTable {
+    index: HashMap<HashKey, usize>,
+    entries: Vec<(Value, Value)>,
+    /// `HashKey::new` for tables compared by `equal?`, or `HashKey::identity` for `eqv?`
+    key: fn(&Value) -> Result<HashKey, Exception>,
+}
+
+impl HashTable {
+    pub fn new(key: fn(&Value) -> Result<HashKey, Exception>) -> HashTable {
+        HashTable {
+            index: HashMap::new(),
+            entries: Vec::new(),
+            key,
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, Exception> {
+        Ok(self
+            .index
+            .get(&(self.key)(key)?)
+            .map(|&i| self.entries[i].1.clone()))
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), Exception> {
+        let hash_key = (self.key)(&key)?;
+        match self.index.get(&hash_key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(hash_key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Result<(), Exception> {
+        if let Some(i) = self.index.remove(&(self.key)(key)?) {
+            self.entries.swap_remove(i);
+            if let Some((moved, _)) = self.entries.get(i) {
+                self.index.insert((self.key)(moved)?, i);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+fn table<'a>(name: &str, v: &'a Value) -> Result<&'a Rc<RefCell<HashTable>>, Exception> {
+    match v {
+        Value::HashTable(t) => Ok(t),
+        v => Err(format!("Expected a hash table to `{}`, got {:#?}", name, v).into()),
+    }
+}
+
+/// A snapshot of a table's entries, so that procedures called while iterating may modify it.
+fn entries(name: &str, args: &[Value]) -> Result<Vec<(Value, Value)>, Exception> {
+    match args {
+        [t] => Ok(table(name, t)?.borrow().entries.clone()),
+        _ => Err(format!("Expected one argument to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+/// `(make-hash-table [equiv])` makes a table comparing keys by `equal?` (the default), or
+/// by `eqv?` or `eq?`.
+fn make_hash_table(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let key = match args {
+        [] => HashKey::new,
+        [Value::Builtin(name)] if name == "equal?" => HashKey::new,
+        [Value::Builtin(name)] if name == "eqv?" || name == "eq?" => HashKey::identity,
+        _ => {
+            return Err(format!(
+                "Expected `equal?`, `eqv?`, `eq?` or nothing to `make-hash-table`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    Ok(Value::HashTable(Rc::new(RefCell::new(HashTable::new(key)))))
+}
+
+fn hash_table_set(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t, key, value] => {
+            table("hash-table-set!", t)?
+                .borrow_mut()
+                .insert(key.clone(), value.clone())?;
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a hash table, a key and a value to `hash-table-set!`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// Calls `default` if it is a procedure (a thunk), or else returns it as is.
+fn default_value(default: &Value, env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match default {
+        f if types::is_procedure(f) => apply_procedure(default, &[], env),
+        v => Ok(v.clone()),
+    }
+}
+
+/// `(hash-table-ref t key [default])` looks up `key`, falling back on `default` (which may be
+/// a thunk) if it is missing. Without a default, a missing key is an error.
+fn hash_table_ref(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t, key, rest @ ..] if rest.len() <= 1 => {
+            let found = table("hash-table-ref", t)?.borrow().get(key)?;
+            match (found, rest.first()) {
+                (Some(v), _) => Ok(v),
+                (None, Some(default)) => default_value(default, env),
+                (None, None) => Err(format!("Key {} not found in hash table!", key).into()),
+            }
+        }
+        _ => Err(format!(
+            "Expected a hash table, a key and an optional default to `hash-table-ref`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn hash_table_ref_default(
+    args: &[Value],
+    _: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    match args {
+        [t, key, default] => Ok(table("hash-table-ref/default", t)?
+            .borrow()
+            .get(key)?
+            .unwrap_or_else(|| default.clone())),
+        _ => Err(format!(
+            "Expected a hash table, a key and a default to `hash-table-ref/default`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn hash_table_delete(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t, key] => {
+            table("hash-table-delete!", t)?.borrow_mut().remove(key)?;
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a hash table and a key to `hash-table-delete!`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn hash_table_contains(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t, key] => Ok(Value::Bool(
+            table("hash-table-contains?", t)?
+                .borrow()
+                .get(key)?
+                .is_some(),
+        )),
+        _ => Err(format!(
+            "Expected a hash table and a key to `hash-table-contains?`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn hash_table_count(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t] => Ok(Value::Integer(
+            table("hash-table-count", t)?.borrow().len() as isize
+        )),
+        _ => Err(format!(
+            "Expected one argument to `hash-table-count`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(hash-table-update! t key proc [default])` replaces the value of `key` with the result of
+/// calling `proc` on it. A missing key starts from `default` (a value or thunk), or is an error.
+fn hash_table_update(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t, key, f, rest @ ..] if rest.len() <= 1 => {
+            let t = table("hash-table-update!", t)?;
+            let found = t.borrow().get(key)?;
+            let current = match (found, rest.first()) {
+                (Some(v), _) => v,
+                (None, Some(default)) => default_value(default, env)?,
+                (None, None) => return Err(format!("Key {} not found in hash table!", key).into()),
+            };
+            let updated = apply_procedure(f, &[current], env)?;
+            t.borrow_mut().insert(key.clone(), updated)?;
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a hash table, a key, a procedure and an optional default to `hash-table-update!`, got {:#?}",
+            args
+        ).into()),
+    }
+}
+
+fn hash_table_update_default(
+    args: &[Value],
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    match args {
+        [t, key, f, default] => {
+            let t = table("hash-table-update!/default", t)?;
+            let current = t.borrow().get(key)?.unwrap_or_else(|| default.clone());
+            let updated = apply_procedure(f, &[current], env)?;
+            t.borrow_mut().insert(key.clone(), updated)?;
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a hash table, a key, a procedure and a default to `hash-table-update!/default`, got {:#?}",
+            args
+        ).into()),
+    }
+}
+
+/// `(hash-table-walk t proc)` calls `proc` on each key and value.
+fn hash_table_walk(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t, f] => {
+            for (k, v) in entries("hash-table-walk", std::slice::from_ref(t))? {
+                apply_procedure(f, &[k, v], env)?;
+            }
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a hash table and a procedure to `hash-table-walk`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(hash-table-fold t kons knil)` folds `(kons key value acc)` over the entries.
+fn hash_table_fold(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [t, f, init] => entries("hash-table-fold", std::slice::from_ref(t))?
+            .into_iter()
+            .try_fold(init.clone(), |acc, (k, v)| apply_procedure(f, &[k, v, acc], env)),
+        _ => Err(format!(
+            "Expected a hash table, a procedure and an initial value to `hash-table-fold`, got {:#?}",
+            args
+        ).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn keys_are_compared_by_contents() {
+        let source = "(define t (make-hash-table))
+            (hash-table-set! t 'a 1)
+            (hash-table-set! t (list 1 2) 'l)
+            (hash-table-set! t 1 'int)
+            (hash-table-set! t 1.0 'float)
+            (list (hash-table-ref t 'a) (hash-table-ref t (list 1 2)) (hash-table-ref t 1) (hash-table-ref t 1.0))";
+        assert_eq!(run(source), Ok("(1 l int float)".into()));
+        assert_eq!(
+            run("(define e (make-hash-table eqv?)) (hash-table-set! e (list 1) 'x) (hash-table-ref/default e (list 1) 'none)"),
+            Ok("none".into())
+        );
+    }
+
+    #[test]
+    fn defaults_and_updates() {
+        let source = "(define t (make-hash-table))
+            (hash-table-update!/default t 'count (lambda (n) (+ n 1)) 0)
+            (hash-table-update!/default t 'count (lambda (n) (+ n 1)) 0)
+            (list (hash-table-ref t 'count) (hash-table-ref/default t 'z 0) (hash-table-ref t 'z (lambda () 'missing)))";
+        assert_eq!(run(source), Ok("(2 0 missing)".into()));
+        assert!(run("(hash-table-ref (make-hash-table) 'z)").is_err());
+        let source = "(define t (make-hash-table))
+            (hash-table-set! t 'a 1)
+            (hash-table-set! t 'b 2)
+            (hash-table-delete! t 'a)
+            (list (hash-table-contains? t 'a) (hash-table-count t) (hash-table-fold t (lambda (k v acc) (+ v acc)) 0))";
+        assert_eq!(run(source), Ok("(#f 1 2)".into()));
+    }
+
+    #[test]
+    fn cyclic_keys_need_identity_tables() {
+        let cyclic = "(define c (list 1)) (set-cdr! c c)";
+        assert!(run(&format!(
+            "{} (hash-table-set! (make-hash-table) c 1)",
+            cyclic
+        ))
+        .is_err());
+        assert_eq!(
+            run(&format!(
+                "{} (define e (make-hash-table eq?)) (hash-table-set! e c 'cyclic) (hash-table-ref e c)",
+                cyclic
+            )),
+            Ok("cyclic".into())
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use crate::environments;
+use crate::eval::{eval_expression, list_to_vec, lookup_identifier, Value};
+use crate::exceptions::Exception;
+use crate::loader;
+use crate::parser::{datum_to_expression, parse_all, Expression};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// A parsed `(define-library (name ...) declaration ...)` form.
+#[derive(Clone, Debug)]
+pub struct LibrarySpec {
+    name: LibraryName,
+    /// Each exported binding's name inside the library and the name it is exported as
+    exports: Vec<(String, String)>,
+    imports: Vec<ImportSet>,
+    body: Vec<Expression>,
+}
+
+/// The parts of a library's name, like `(utils strings)`, which also give the path of the
+/// file it is looked for in.
+#[derive(Clone, Debug)]
+pub struct LibraryName(Vec<String>);
+
+/// One of the sets of bindings named by `import`.
+#[derive(Clone, Debug)]
+pub enum ImportSet {
+    Library(LibraryName),
+    Only(Box<ImportSet>, Vec<String>),
+    Except(Box<ImportSet>, Vec<String>),
+    Prefix(Box<ImportSet>, String),
+    Rename(Box<ImportSet>, Vec<(String, String)>),
+}
+
+fn identifier(what: &str, v: &Value) -> Result<String, Exception> {
+    match v {
+        Value::Symbol(s) => Ok(s.clone()),
+        v => Err(format!("Expected an identifier for the {}, got {}", what, v).into()),
+    }
+}
+
+fn identifiers(what: &str, vs: &[Value]) -> Result<Vec<String>, Exception> {
+    vs.iter().map(|v| identifier(what, v)).collect()
+}
+
+/// A `(name new-name)` pair, as in `rename`.
+fn renaming(form: &str, v: &Value) -> Result<(String, String), Exception> {
+    match list_to_vec(v).as_deref() {
+        Some([from, to]) => Ok((identifier(form, from)?, identifier(form, to)?)),
+        _ => Err(format!("Expected (name new-name) in {}, got {}", form, v).into()),
+    }
+}
+
+impl LibraryName {
+    fn new(v: &Value) -> Result<LibraryName, Exception> {
+        let parts = list_to_vec(v)
+            .filter(|parts| !parts.is_empty())
+            .ok_or_else(|| format!("Expected a library name like (utils strings), got {}", v))?;
+        parts
+            .iter()
+            .map(|part| match part {
+                Value::Symbol(s) => Ok(s.clone()),
+                Value::Integer(n) if *n >= 0 => Ok(n.to_string()),
+                v => Err(format!("Invalid part {} of a library name", v).into()),
+            })
+            .collect::<Result<Vec<String>, Exception>>()
+            .map(LibraryName)
+    }
+
+    /// The standard libraries, like `(scheme base)`, whose bindings are the builtins and so are
+    /// always available.
+    fn is_builtin(&self) -> bool {
+        self.0[0] == "scheme"
+    }
+}
+
+impl std::fmt::Display for LibraryName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({})", self.0.join(" "))
+    }
+}
+
+impl LibrarySpec {
+    /// Reads the parts of a `define-library` form following the keyword, as data. The body is
+    /// turned into code right away, so any `include` in it is relative to the file defining the
+    /// library.
+    pub fn new(parts: &[Value]) -> Result<LibrarySpec, Exception> {
+        let (name, declarations) = match parts {
+            [name, declarations @ ..] => (LibraryName::new(name)?, declarations),
+            _ => return Err("Expected a name in define-library!".into()),
+        };
+        let mut spec = LibrarySpec {
+            name,
+            exports: Vec::new(),
+            imports: Vec::new(),
+            body: Vec::new(),
+        };
+        for declaration in declarations {
+            let parts = list_to_vec(declaration).unwrap_or_default();
+            match parts.split_first() {
+                Some((Value::Symbol(s), specs)) if s == "export" => {
+                    for spec_part in specs {
+                        spec.exports.push(match spec_part {
+                            Value::Symbol(s) => (s.clone(), s.clone()),
+                            v => match list_to_vec(v).as_deref() {
+                                Some([Value::Symbol(r), from, to]) if r == "rename" => {
+                                    (identifier("export", from)?, identifier("export", to)?)
+                                }
+                                _ => return Err(format!("Invalid export {}", v).into()),
+                            },
+                        });
+                    }
+                }
+                Some((Value::Symbol(s), sets)) if s == "import" => {
+                    for set in sets {
+                        spec.imports.push(ImportSet::new(set)?);
+                    }
+                }
+                Some((Value::Symbol(s), forms)) if s == "begin" => {
+                    for form in forms {
+                        spec.body.push(datum_to_expression(form)?);
+                    }
+                }
+                Some((Value::Symbol(s), _)) if s == "include" => {
+                    spec.body.push(datum_to_expression(declaration)?);
+                }
+                _ => {
+                    return Err(
+                        format!("Invalid declaration {} in define-library", declaration).into(),
+                    )
+                }
+            }
+        }
+        Ok(spec)
+    }
+}
+
+impl ImportSet {
+    pub fn new(v: &Value) -> Result<ImportSet, Exception> {
+        let parts = list_to_vec(v).unwrap_or_default();
+        let set = |v| ImportSet::new(v).map(Box::new);
+        Ok(match parts.as_slice() {
+            [Value::Symbol(s), inner, names @ ..] if s == "only" => {
+                ImportSet::Only(set(inner)?, identifiers("only", names)?)
+            }
+            [Value::Symbol(s), inner, names @ ..] if s == "except" => {
+                ImportSet::Except(set(inner)?, identifiers("except", names)?)
+            }
+            [Value::Symbol(s), inner, prefix] if s == "prefix" => {
+                ImportSet::Prefix(set(inner)?, identifier("prefix", prefix)?)
+            }
+            [Value::Symbol(s), inner, renamings @ ..] if s == "rename" => ImportSet::Rename(
+                set(inner)?,
+                renamings
+                    .iter()
+                    .map(|r| renaming("rename", r))
+                    .collect::<Result<Vec<_>, Exception>>()?,
+            ),
+            _ => ImportSet::Library(LibraryName::new(v)?),
+        })
+    }
+
+    fn library(&self) -> &LibraryName {
+        match self {
+            ImportSet::Library(name) => name,
+            ImportSet::Only(set, _)
+            | ImportSet::Except(set, _)
+            | ImportSet::Prefix(set, _)
+            | ImportSet::Rename(set, _) => set.library(),
+        }
+    }
+
+    /// The bindings this set names, by the names they are imported as.
+    fn bindings(&self) -> Result<HashMap<String, Value>, Exception> {
+        let check = |bindings: &HashMap<String, Value>, name: &String| {
+            if bindings.contains_key(name) {
+                Ok(())
+            } else {
+                Err(Exception::from(format!(
+                    "{} is not exported by {}",
+                    name,
+                    self.library()
+                )))
+            }
+        };
+        Ok(match self {
+            ImportSet::Library(name) => (*exports(name)?).clone(),
+            ImportSet::Only(set, names) => {
+                let mut bindings = set.bindings()?;
+                for name in names {
+                    check(&bindings, name)?;
+                }
+                bindings.retain(|name, _| names.contains(name));
+                bindings
+            }
+            ImportSet::Except(set, names) => {
+                let mut bindings = set.bindings()?;
+                for name in names {
+                    check(&bindings, name)?;
+                    bindings.remove(name);
+                }
+                bindings
+            }
+            ImportSet::Prefix(set, prefix) => set
+                .bindings()?
+                .into_iter()
+                .map(|(name, v)| (format!("{}{}", prefix, name), v))
+                .collect(),
+            ImportSet::Rename(set, renamings) => {
+                let mut bindings = set.bindings()?;
+                for (from, to) in renamings {
+                    check(&bindings, from)?;
+                    let v = bindings.remove(from).unwrap();
+                    bindings.insert(to.clone(), v);
+                }
+                bindings
+            }
+        })
+    }
+}
+
+#[derive(Clone)]
+enum Library {
+    Defined(Rc<LibrarySpec>),
+    /// Being evaluated, so importing it again means libraries import each other
+    Evaluating,
+    /// The library's exports, kept so that it is only ever evaluated once
+    Evaluated(Rc<HashMap<String, Value>>),
+}
+
+thread_local! {
+    static LIBRARIES: RefCell<HashMap<String, Library>> = RefCell::new(HashMap::new());
+    /// The directories searched for libraries that haven't been defined yet
+    static SEARCH_PATH: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn add_search_path(dir: PathBuf) {
+    SEARCH_PATH.with(|path| path.borrow_mut().push(dir));
+}
+
+/// Makes the library available to `import`. It isn't evaluated until it is first imported.
+pub fn define_library(spec: &LibrarySpec) {
+    let key = spec.name.to_string();
+    LIBRARIES.with(|libraries| {
+        libraries
+            .borrow_mut()
+            .insert(key, Library::Defined(Rc::new(spec.clone())))
+    });
+}
+
+/// Adds the bindings named by `sets` to `env`.
+pub fn import(sets: &[ImportSet], env: &mut HashMap<String, Value>) -> Result<(), Exception> {
+    for set in sets {
+        if !set.library().is_builtin() {
+            env.extend(set.bindings()?);
+        }
+    }
+    Ok(())
+}
+
+/// Looks for the file of a library that hasn't been defined: `(utils strings)` is in
+/// `utils/strings.sld` or `utils/strings.scm`, next to the importing file or in one of the
+/// search path's directories.
+fn find_library(name: &LibraryName) -> Option<PathBuf> {
+    let base = name.0.join("/");
+    let dirs = SEARCH_PATH.with(|path| path.borrow().clone());
+    ["sld", "scm"].iter().find_map(|extension| {
+        let file = format!("{}.{}", base, extension);
+        std::iter::once(loader::resolve(&file))
+            .chain(dirs.iter().map(|dir| dir.join(&file)))
+            .find(|path| path.is_file())
+    })
+}
+
+fn set_library(key: &str, library: Library) {
+    LIBRARIES.with(|libraries| libraries.borrow_mut().insert(key.to_string(), library));
+}
+
+/// The exports of the library called `name`, evaluating it first if this is the first import.
+fn exports(name: &LibraryName) -> Result<Rc<HashMap<String, Value>>, Exception> {
+    let key = name.to_string();
+    let get = || LIBRARIES.with(|libraries| libraries.borrow().get(&key).cloned());
+    if get().is_none() {
+        let path = find_library(name).ok_or_else(|| format!("Library {} not found!", key))?;
+        loader::with_file(&path.to_string_lossy(), |text| {
+            for expr in parse_all(text)? {
+                eval_expression(&expr, &mut HashMap::new())?;
+            }
+            Ok(())
+        })?;
+    }
+    let spec = match get() {
+        Some(Library::Evaluated(exports)) => return Ok(exports),
+        Some(Library::Evaluating) => {
+            return Err(format!(
+                "Library {} imports itself, directly or through other libraries!",
+                key
+            )
+            .into())
+        }
+        Some(Library::Defined(spec)) => spec,
+        None => return Err(format!("The file for {} doesn't define it!", key).into()),
+    };
+    set_library(&key, Library::Evaluating);
+    let res = evaluate(&spec);
+    match &res {
+        Ok(exports) => set_library(&key, Library::Evaluated(exports.clone())),
+        Err(_) => set_library(&key, Library::Defined(spec)),
+    }
+    res
+}
+
+/// Evaluates the body of a library in an environment of its own, giving its exports. The
+/// library can't see the program's top level either.
+fn evaluate(spec: &LibrarySpec) -> Result<Rc<HashMap<String, Value>>, Exception> {
+    environments::with_top_level(Rc::default(), || evaluate_body(spec))
+}
+
+fn evaluate_body(spec: &LibrarySpec) -> Result<Rc<HashMap<String, Value>>, Exception> {
+    let mut env = HashMap::new();
+    import(&spec.imports, &mut env)?;
+    for expr in &spec.body {
+        eval_expression(expr, &mut env)?;
+    }
+    spec.exports
+        .iter()
+        .map(|(internal, external)| {
+            let v = lookup_identifier(internal, &env).ok_or_else(|| {
+                format!(
+                    "Library {} exports {}, which it doesn't define!",
+                    spec.name, internal
+                )
+            })?;
+            Ok((external.clone(), v))
+        })
+        .collect::<Result<HashMap<String, Value>, Exception>>()
+        .map(Rc::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::add_search_path;
+    use crate::eval::run;
+
+    const SHAPES: &str = "(define-library (utils shapes)
+        (export area (rename perimeter edge))
+        (begin
+          (define side 3)
+          (define area (lambda (x) (* x x)))
+          (define perimeter (lambda (x) (* 4 x)))))";
+
+    #[test]
+    fn import_sets() {
+        assert_eq!(
+            run(&format!(
+                "{} (import (prefix (only (utils shapes) area) s:)) (s:area 4)",
+                SHAPES
+            )),
+            Ok("16".into())
+        );
+        assert_eq!(
+            run(&format!(
+                "{} (import (rename (utils shapes) (edge e))) (e 2)",
+                SHAPES
+            )),
+            Ok("8".into())
+        );
+        assert!(run(&format!(
+            "{} (import (except (utils shapes) edge)) edge",
+            SHAPES
+        ))
+        .is_err());
+        assert!(run(&format!("{} (import (utils shapes)) side", SHAPES)).is_err());
+    }
+
+    #[test]
+    fn libraries_must_define_their_exports() {
+        assert_eq!(
+            run("(define-library (bad) (export nothing) (begin (define x 1))) (import (bad))"),
+            Err("Library (bad) exports nothing, which it doesn't define!".into())
+        );
+        assert!(run("(import (no such library))").is_err());
+        assert_eq!(run("(import (scheme base)) (car '(1))"), Ok("1".into()));
+    }
+
+    #[test]
+    fn libraries_are_found_on_the_search_path() {
+        let dir = std::env::temp_dir().join("libraries_are_found_on_the_search_path");
+        std::fs::create_dir_all(dir.join("found")).unwrap();
+        std::fs::write(
+            dir.join("found").join("here.sld"),
+            "(define-library (found here) (export answer) (begin (define answer 42)))",
+        )
+        .unwrap();
+        add_search_path(dir);
+        assert_eq!(run("(import (found here)) answer"), Ok("42".into()));
+    }
+}
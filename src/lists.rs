@@ -0,0 +1,593 @@
+use crate::equivalence::{eq, equal, eqv};
+use crate::eval::{apply_procedure, cons, list_to_vec, vec_to_list, Builtin, Value};
+use crate::exceptions::Exception;
+use crate::persistent;
+use crate::types;
+use crate::vectors::new_vector;
+use std::collections::HashMap;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "cons" => |args, _| match args {
+            [car, cdr] => Ok(cons(car.clone(), cdr.clone())),
+            _ => Err(format!("Expected two arguments to `cons`, got {:#?}", args).into()),
+        },
+        "car" => |args, _| match args {
+            [Value::Pair(p)] => Ok(p.borrow().car.clone()),
+            [v] => Err(format!("{:#?} not a pair!", v).into()),
+            _ => Err(format!("Expected one argument to `car`, got {:#?}", args).into()),
+        },
+        "cdr" => |args, _| match args {
+            [Value::Pair(p)] => Ok(p.borrow().cdr.clone()),
+            [v] => Err(format!("{:#?} not a pair!", v).into()),
+            _ => Err(format!("Expected one argument to `cdr`, got {:#?}", args).into()),
+        },
+        "set-car!" => |args, _| match args {
+            [Value::Pair(p), v] => {
+                p.borrow_mut().car = v.clone();
+                Ok(Value::Nil)
+            }
+            _ => Err(format!("Expected a pair and a value to `set-car!`, got {:#?}", args).into()),
+        },
+        "set-cdr!" => |args, _| match args {
+            [Value::Pair(p), v] => {
+                p.borrow_mut().cdr = v.clone();
+                Ok(Value::Nil)
+            }
+            _ => Err(format!("Expected a pair and a value to `set-cdr!`, got {:#?}", args).into()),
+        },
+        "list" => |args, _| Ok(vec_to_list(args.to_vec())),
+        "null?" => |args, _| match args {
+            [v] => Ok(Value::Bool(matches!(v, Value::Nil))),
+            _ => Err(format!("Expected one argument to `null?`, got {:#?}", args).into()),
+        },
+        "length" => |args, _| match args {
+            [l] => Ok(Value::Integer(list("length", l)?.len() as isize)),
+            _ => Err(format!("Expected one argument to `length`, got {:#?}", args).into()),
+        },
+        "append" => append,
+        "reverse" => |args, _| match args {
+            [l] => Ok(vec_to_list(list("reverse", l)?.into_iter().rev().collect())),
+            _ => Err(format!("Expected one argument to `reverse`, got {:#?}", args).into()),
+        },
+        "list-ref" => list_ref,
+        "list-tail" => list_tail,
+        "last" => |args, _| match args {
+            [l] => list("last", l)?
+                .pop()
+                .ok_or_else(|| "Expected a non-empty list to `last`".into()),
+            _ => Err(format!("Expected one argument to `last`, got {:#?}", args).into()),
+        },
+        "iota" => iota,
+        "map" => map,
+        "for-each" => for_each,
+        "filter" => |args, env| {
+            let (included, _) = partition_list("filter", args, env)?;
+            Ok(vec_to_list(included))
+        },
+        "remove" => |args, env| {
+            let (_, excluded) = partition_list("remove", args, env)?;
+            Ok(vec_to_list(excluded))
+        },
+        "partition" => |args, env| {
+            let (included, excluded) = partition_list("partition", args, env)?;
+            Ok(cons(vec_to_list(included), vec_to_list(excluded)))
+        },
+        "reduce" => reduce,
+        "fold-left" => fold_left,
+        "fold-right" => fold_right,
+        "any" => any,
+        "every" => every,
+        "member" => |args, env| member("member", equal, args, env),
+        "memv" => |args, env| member("memv", eqv, args, env),
+        "memq" => |args, env| member("memq", eq, args, env),
+        "assoc" => |args, env| match args {
+            args if persistent_update(args) => persistent::assoc(args, env),
+            _ => assoc("assoc", equal, args, env),
+        },
+        "assv" => |args, env| assoc("assv", eqv, args, env),
+        "assq" => |args, env| assoc("assq", eq, args, env),
+        "delete" => delete,
+        "sort" => sort,
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// The elements of a proper list, or of a persistent collection as `seq` lists them, so that the
+/// list procedures work on those too.
+fn list(name: &str, l: &Value) -> Result<Vec<Value>, Exception> {
+    list_to_vec(l)
+        .or_else(|| persistent::elements(l))
+        .ok_or_else(|| format!("Expected a list to `{}`, got {:#?}", name, l).into())
+}
+
+fn truthy(v: &Value) -> bool {
+    !matches!(v, Value::Bool(false))
+}
+
+/// Appends lists. Every argument but the last must be a proper list, and the last is shared
+/// as the tail of the result.
+fn append(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (last, init) = match args.split_last() {
+        Some(split) => split,
+        None => return Ok(Value::Nil),
+    };
+    let mut res = last.clone();
+    for l in init.iter().rev() {
+        res = list("append", l)?
+            .into_iter()
+            .rfold(res, |acc, x| cons(x, acc));
+    }
+    Ok(res)
+}
+
+fn index_arg(name: &str, v: &Value) -> Result<usize, Exception> {
+    match v {
+        Value::Integer(n) if *n >= 0 => Ok(*n as usize),
+        v => Err(format!("Expected an index to `{}`, got {:#?}", name, v).into()),
+    }
+}
+
+/// `(list-tail l k)` drops the first `k` pairs of `l`. The rest needn't be a proper list.
+fn list_tail(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [l, k] => {
+            let mut current = l.clone();
+            for _ in 0..index_arg("list-tail", k)? {
+                current = match &current {
+                    Value::Pair(p) => p.borrow().cdr.clone(),
+                    _ => return Err(format!("Index {} out of range for `list-tail`", k).into()),
+                };
+            }
+            Ok(current)
+        }
+        _ => Err(format!(
+            "Expected a list and an index to `list-tail`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn list_ref(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [_, k] => match list_tail(args, env) {
+            Ok(Value::Pair(p)) => Ok(p.borrow().car.clone()),
+            _ => Err(format!("Index {} out of range for `list-ref`", k).into()),
+        },
+        _ => Err(format!(
+            "Expected a list and an index to `list-ref`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(iota count [start [step]])` lists `count` numbers from `start` (default 0), `step`
+/// (default 1) apart. The numbers are integers unless `start` or `step` is a float.
+fn iota(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let count = match args.first() {
+        Some(n) if args.len() <= 3 => index_arg("iota", n)?,
+        _ => {
+            return Err(format!(
+                "Expected a count, an optional start and an optional step to `iota`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    let start = args.get(1).cloned().unwrap_or(Value::Integer(0));
+    let step = args.get(2).cloned().unwrap_or(Value::Integer(1));
+    let res = match (start, step) {
+        (Value::Integer(start), Value::Integer(step)) => (0..count)
+            .map(|i| {
+                (i as isize)
+                    .checked_mul(step)
+                    .and_then(|n| n.checked_add(start))
+                    .map(Value::Integer)
+                    .ok_or_else(|| "Integer overflow in `iota`".into())
+            })
+            .collect::<Result<Vec<Value>, Exception>>()?,
+        (start, step) => {
+            let float = |v: &Value| match v {
+                Value::Integer(n) => Ok(*n as f64),
+                Value::Number(n) => Ok(*n),
+                v => Err(format!("Expected a real number to `iota`, got {:#?}", v)),
+            };
+            let (start, step) = (float(&start)?, float(&step)?);
+            (0..count)
+                .map(|i| Value::Number(start + i as f64 * step))
+                .collect()
+        }
+    };
+    Ok(vec_to_list(res))
+}
+
+/// Splits `(proc l1 l2 ...)` arguments into the procedure and the lists' elements, truncated
+/// to the length of the shortest list.
+fn procedure_and_lists<'a>(
+    name: &str,
+    args: &'a [Value],
+) -> Result<(&'a Value, Vec<Vec<Value>>), Exception> {
+    match args {
+        [f, lists @ ..] if !lists.is_empty() => {
+            let mut lists = lists
+                .iter()
+                .map(|l| list(name, l))
+                .collect::<Result<Vec<Vec<Value>>, Exception>>()?;
+            let len = lists.iter().map(|l| l.len()).min().unwrap_or(0);
+            lists.iter_mut().for_each(|l| l.truncate(len));
+            Ok((f, lists))
+        }
+        _ => Err(format!(
+            "Expected a procedure and at least one list to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// The arguments for the `i`th call of a procedure mapped over `lists`.
+fn nth_elements(lists: &[Vec<Value>], i: usize) -> Vec<Value> {
+    lists.iter().map(|l| l[i].clone()).collect()
+}
+
+fn map(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, lists) = procedure_and_lists("map", args)?;
+    let mut res = Vec::with_capacity(lists[0].len());
+    for i in 0..lists[0].len() {
+        res.push(apply_procedure(f, &nth_elements(&lists, i), env)?);
+    }
+    Ok(vec_to_list(res))
+}
+
+fn for_each(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, lists) = procedure_and_lists("for-each", args)?;
+    for i in 0..lists[0].len() {
+        apply_procedure(f, &nth_elements(&lists, i), env)?;
+    }
+    Ok(Value::Nil)
+}
+
+/// Splits a `(pred l)` list into the elements that satisfy `pred` and those that don't.
+fn partition_list(
+    name: &str,
+    args: &[Value],
+    env: &mut HashMap<String, Value>,
+) -> Result<(Vec<Value>, Vec<Value>), Exception> {
+    match args {
+        [pred, l] => {
+            let (mut included, mut excluded) = (Vec::new(), Vec::new());
+            for x in list(name, l)? {
+                if truthy(&apply_procedure(pred, std::slice::from_ref(&x), env)?) {
+                    included.push(x);
+                } else {
+                    excluded.push(x);
+                }
+            }
+            Ok((included, excluded))
+        }
+        _ => Err(format!(
+            "Expected a predicate and a list to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// `(reduce f ridentity l)` folds `(f x acc)` over `l`, starting from its first element.
+/// An empty list gives `ridentity`.
+fn reduce(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [f, ridentity, l] => {
+            let mut elements = list("reduce", l)?.into_iter();
+            match elements.next() {
+                Some(first) => {
+                    elements.try_fold(first, |acc, x| apply_procedure(f, &[x, acc], env))
+                }
+                None => Ok(ridentity.clone()),
+            }
+        }
+        _ => Err(format!(
+            "Expected a procedure, a default and a list to `reduce`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// Splits `(f init l1 l2 ...)` fold arguments like `procedure_and_lists`.
+fn fold_args<'a>(
+    name: &str,
+    args: &'a [Value],
+) -> Result<(&'a Value, Value, Vec<Vec<Value>>), Exception> {
+    match args {
+        [f, init, lists @ ..] if !lists.is_empty() => {
+            let mut rest = vec![f.clone()];
+            rest.extend_from_slice(lists);
+            let (_, lists) = procedure_and_lists(name, &rest)?;
+            Ok((f, init.clone(), lists))
+        }
+        _ => Err(format!(
+            "Expected a procedure, an initial value and at least one list to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// `(fold-left f init l1 l2 ...)` calls `(f acc x1 x2 ...)` from the left.
+fn fold_left(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, mut acc, lists) = fold_args("fold-left", args)?;
+    for i in 0..lists[0].len() {
+        let mut call = vec![acc];
+        call.extend(nth_elements(&lists, i));
+        acc = apply_procedure(f, &call, env)?;
+    }
+    Ok(acc)
+}
+
+/// `(fold-right f init l1 l2 ...)` calls `(f x1 x2 ... acc)` from the right.
+fn fold_right(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, mut acc, lists) = fold_args("fold-right", args)?;
+    for i in (0..lists[0].len()).rev() {
+        let mut call = nth_elements(&lists, i);
+        call.push(acc);
+        acc = apply_procedure(f, &call, env)?;
+    }
+    Ok(acc)
+}
+
+/// `(any pred l1 l2 ...)` returns the first true result of `pred`, or `#f`.
+fn any(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, lists) = procedure_and_lists("any", args)?;
+    for i in 0..lists[0].len() {
+        let res = apply_procedure(f, &nth_elements(&lists, i), env)?;
+        if truthy(&res) {
+            return Ok(res);
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+/// `(every pred l1 l2 ...)` returns `#f` if `pred` ever does, or else its last result (`#t`
+/// for empty lists).
+fn every(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, lists) = procedure_and_lists("every", args)?;
+    let mut res = Value::Bool(true);
+    for i in 0..lists[0].len() {
+        res = apply_procedure(f, &nth_elements(&lists, i), env)?;
+        if !truthy(&res) {
+            break;
+        }
+    }
+    Ok(res)
+}
+
+/// Compares `x` with `y` by `compare`, or by the procedure `custom` if one was passed.
+fn same(
+    compare: fn(&Value, &Value) -> bool,
+    custom: Option<&Value>,
+    x: &Value,
+    y: &Value,
+    env: &mut HashMap<String, Value>,
+) -> Result<bool, Exception> {
+    match custom {
+        Some(f) => Ok(truthy(&apply_procedure(f, &[x.clone(), y.clone()], env)?)),
+        None => Ok(compare(x, y)),
+    }
+}
+
+/// Whether `assoc` was given a persistent collection with keys and values to set, rather than a
+/// key (which may be a persistent collection too) and an association list to look it up in,
+/// with maybe a comparison procedure.
+fn persistent_update(args: &[Value]) -> bool {
+    match args {
+        [Value::PMap(_) | Value::PVector(_), _, _, ..] => {
+            !matches!(args, [_, Value::Pair(_) | Value::Nil, f] if types::is_procedure(f))
+        }
+        _ => false,
+    }
+}
+
+/// `(member x l [compare])` returns the first tail of `l` whose car is `x`, or `#f`. The
+/// elements of a persistent collection are searched in the order `seq` lists them, giving a
+/// list of the rest of them.
+fn member(
+    name: &str,
+    compare: fn(&Value, &Value) -> bool,
+    args: &[Value],
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    match args {
+        [x, l, custom @ ..] if custom.len() <= 1 && !matches!(l, Value::Pair(_) | Value::Nil) => {
+            let elements = list(name, l)?;
+            for (i, y) in elements.iter().enumerate() {
+                if same(compare, custom.first(), x, y, env)? {
+                    return Ok(vec_to_list(elements[i..].to_vec()));
+                }
+            }
+            Ok(Value::Bool(false))
+        }
+        [x, l, custom @ ..] if custom.len() <= 1 => {
+            let mut current = l.clone();
+            while let Value::Pair(p) = &current {
+                let (car, cdr) = {
+                    let p = p.borrow();
+                    (p.car.clone(), p.cdr.clone())
+                };
+                if same(compare, custom.first(), x, &car, env)? {
+                    return Ok(current);
+                }
+                current = cdr;
+            }
+            match current {
+                Value::Nil => Ok(Value::Bool(false)),
+                _ => Err(format!("Expected a list to `{}`, got {:#?}", name, l).into()),
+            }
+        }
+        _ => Err(format!("Expected a value and a list to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+/// `(assoc key alist [compare])` returns the first pair in `alist` whose car is `key`, or `#f`.
+fn assoc(
+    name: &str,
+    compare: fn(&Value, &Value) -> bool,
+    args: &[Value],
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    match args {
+        [key, alist, custom @ ..] if custom.len() <= 1 => {
+            for pair in list(name, alist)? {
+                match &pair {
+                    Value::Pair(p) => {
+                        let car = p.borrow().car.clone();
+                        if same(compare, custom.first(), key, &car, env)? {
+                            return Ok(pair);
+                        }
+                    }
+                    v => {
+                        return Err(format!(
+                            "Expected an association list to `{}`, got the element {:#?}",
+                            name, v
+                        )
+                        .into())
+                    }
+                }
+            }
+            Ok(Value::Bool(false))
+        }
+        _ => Err(format!(
+            "Expected a key and an association list to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// `(delete x l [compare])` removes every element equal to `x`.
+fn delete(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [x, l, custom @ ..] if custom.len() <= 1 => {
+            let mut res = Vec::new();
+            for y in list("delete", l)? {
+                if !same(equal, custom.first(), x, &y, env)? {
+                    res.push(y);
+                }
+            }
+            Ok(vec_to_list(res))
+        }
+        _ => Err(format!("Expected a value and a list to `delete`, got {:#?}", args).into()),
+    }
+}
+
+/// `(sort l less?)` sorts a list, or returns a sorted copy of a vector. The sort is a
+/// bottom-up merge sort, so it is stable and only asks `less?` about pairs of elements.
+fn sort(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (elements, less) = match args {
+        [Value::Vector(v), less] => (v.borrow().clone(), less),
+        [l, less] => (list("sort", l)?, less),
+        _ => {
+            return Err(format!(
+                "Expected a list or vector and a procedure to `sort`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    let mut runs = elements.into_iter().map(|x| vec![x]).collect::<Vec<_>>();
+    while runs.len() > 1 {
+        let mut merged = Vec::with_capacity(runs.len() / 2 + 1);
+        let mut runs_iter = runs.into_iter();
+        while let Some(left) = runs_iter.next() {
+            match runs_iter.next() {
+                Some(right) => merged.push(merge(left, right, less, env)?),
+                None => merged.push(left),
+            }
+        }
+        runs = merged;
+    }
+    let sorted = runs.pop().unwrap_or_default();
+    Ok(match &args[0] {
+        Value::Vector(_) => new_vector(sorted),
+        _ => vec_to_list(sorted),
+    })
+}
+
+/// Merges two sorted runs, taking from `left` unless `right`'s element is strictly less.
+fn merge(
+    left: Vec<Value>,
+    right: Vec<Value>,
+    less: &Value,
+    env: &mut HashMap<String, Value>,
+) -> Result<Vec<Value>, Exception> {
+    let mut res = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if truthy(&apply_procedure(less, &[r.clone(), l.clone()], env)?) {
+            res.extend(right.next());
+        } else {
+            res.extend(left.next());
+        }
+    }
+    res.extend(left);
+    res.extend(right);
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn assoc_looks_up_alists() {
+        assert_eq!(run("(assoc 2 '((1 . a) (2 . b)))"), Ok("(2 . b)".into()));
+        assert_eq!(run("(assoc 3 '((1 . a)))"), Ok("#f".into()));
+        assert_eq!(
+            run("(assoc 2.0 '((1 . a) (2 . b)) =)"),
+            Ok("(2 . b)".into())
+        );
+        assert_eq!(
+            run("(assoc (pvector 1) (list (cons (pvector 1) 'found)))"),
+            Ok("([1] . found)".into())
+        );
+        assert_eq!(
+            run("(assoc (pmap 'a 1) (list (cons (pmap 'a 1) 'found)) equal?)"),
+            Ok("({a 1} . found)".into())
+        );
+    }
+
+    #[test]
+    fn assoc_updates_persistent_collections() {
+        assert_eq!(run("(get (assoc (pmap 'a 1) 'b 2) 'b)"), Ok("2".into()));
+        assert_eq!(run("(assoc (pvector 1 2) 0 'x 2 'y)"), Ok("[x 2 y]".into()));
+        assert!(run("(assoc (pvector 1) 5 'x)").is_err());
+    }
+
+    #[test]
+    fn member_searches_persistent_collections() {
+        assert_eq!(run("(member 2 (pvector 1 2 3))"), Ok("(2 3)".into()));
+        assert_eq!(run("(memv 4 (pvector 1 2 3))"), Ok("#f".into()));
+        assert_eq!(run("(memq 'b (pset 'b))"), Ok("(b)".into()));
+        assert_eq!(run("(member 2.0 (pvector 1 2 3) =)"), Ok("(2 3)".into()));
+        assert_eq!(run("(member 2 '(1 2 3))"), Ok("(2 3)".into()));
+        assert!(run("(member 2 5)").is_err());
+    }
+
+    #[test]
+    fn pairs_are_shared_and_mutable() {
+        assert_eq!(
+            run("(define a (list 1 2)) (define b a) (set-car! b 10) a"),
+            Ok("(10 2)".into())
+        );
+        assert_eq!(
+            run("(define c (list 1 2)) (set-cdr! (cdr c) c) (list-ref c 5)"),
+            Ok("2".into())
+        );
+        assert!(run("(set-car! '() 1)").is_err());
+        assert!(run("(set-cdr! 5 1)").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::environments;
+use crate::eval::{Builtin, Value};
+use crate::exceptions::Exception;
+use crate::parser::parse_all;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "load" => |args, _| match args {
+            [Value::String(path)] => with_file(path, |text| {
+                for expr in parse_all(text)? {
+                    environments::eval_top_level(&expr)?;
+                }
+                Ok(Value::Nil)
+            }),
+            _ => Err(format!("Expected one file name to `load`, got {:#?}", args).into()),
+        },
+        _ => return None,
+    };
+    Some(f)
+}
+
+thread_local! {
+    /// The files being loaded or included, innermost last.
+    static FILES: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Where `path` names, relative to the directory of the file being loaded if there is one.
+pub fn resolve(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    let dir = FILES.with(|files| {
+        files
+            .borrow()
+            .last()
+            .and_then(|file| file.parent())
+            .map(Path::to_path_buf)
+    });
+    match dir {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Reads the file at `path` and passes its contents to `f`, which sees it as the current file.
+/// Paths are resolved relative to the current file, and a file loading itself, directly or
+/// through others, is an error, and so is reading any file in a sandbox.
+pub fn with_file<T, F>(path: &str, f: F) -> Result<T, Exception>
+where
+    F: FnOnce(&str) -> Result<T, Exception>,
+{
+    if environments::sandboxed() {
+        return Err(format!("Can't read {} from a sandbox environment!", path).into());
+    }
+    let resolved = resolve(path);
+    let text = std::fs::read_to_string(&resolved)
+        .map_err(|e| format!("Couldn't read {}: {}", resolved.display(), e))?;
+    // Canonical paths tell whether two differently written paths are the same file
+    let canonical = resolved.canonicalize().unwrap_or(resolved);
+    let cycle = FILES.with(|files| {
+        let files = files.borrow();
+        files.iter().position(|file| *file == canonical).map(|i| {
+            files[i..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|file| file.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        })
+    });
+    if let Some(cycle) = cycle {
+        return Err(format!("Load cycle: {}", cycle).into());
+    }
+    FILES.with(|files| files.borrow_mut().push(canonical));
+    let res = f(&text);
+    FILES.with(|files| files.borrow_mut().pop());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn load_defines_at_the_top_level() {
+        let path = std::env::temp_dir().join("load_defines_at_the_top_level.scm");
+        std::fs::write(&path, "(define loaded 42)").unwrap();
+        let source = format!(
+            "(define f (lambda (path) (load path))) (f {:?}) loaded",
+            path.display().to_string()
+        );
+        assert_eq!(run(&source), Ok("42".into()));
+    }
+
+    #[test]
+    fn include_splices_forms() {
+        let dir = std::env::temp_dir().join("include_splices_forms");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("body.scm"), "(* x 2)").unwrap();
+        let source = format!(
+            "(define f (lambda (x) (include {:?}))) (f 21)",
+            dir.join("body.scm").display().to_string()
+        );
+        assert_eq!(run(&source), Ok("42".into()));
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_loading_file() {
+        let dir = std::env::temp_dir().join("paths_are_relative_to_the_loading_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("outer.scm"), "(load \"inner.scm\")").unwrap();
+        std::fs::write(dir.join("inner.scm"), "(define inner 'found)").unwrap();
+        std::fs::write(dir.join("itself.scm"), "(load \"itself.scm\")").unwrap();
+        let path = |name: &str| format!("{:?}", dir.join(name).display().to_string());
+        assert_eq!(
+            run(&format!("(load {}) inner", path("outer.scm"))),
+            Ok("found".into())
+        );
+        assert!(run(&format!("(load {})", path("itself.scm"))).is_err());
+        assert!(run(&format!("(load {})", path("missing.scm"))).is_err());
+    }
+}
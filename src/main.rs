@@ -1,15 +1,32 @@
+mod bytevectors;
+mod chars;
+mod environments;
+mod equivalence;
 mod eval;
+mod exceptions;
+mod hashtables;
+mod libraries;
+mod lists;
+mod loader;
+mod numeric;
 mod parser;
+mod persistent;
+mod ports;
+mod prelude;
+mod printer;
+mod records;
+mod strings;
 mod tokenizer;
-use crate::eval::{eval_expression, Value};
+mod types;
+mod vectors;
+use crate::environments::eval_top_level;
+use crate::eval::Value;
+use crate::exceptions::Exception;
 use crate::parser::parse_expression;
-use crate::tokenizer::tokenize;
+use crate::tokenizer::{is_incomplete, tokenize};
 
-use std::collections::HashMap;
 use std::io::prelude::*;
 
-// TODO: stl?
-
 #[derive(Debug)]
 struct StdioLinesIterator {
     stdin: std::io::Stdin,
@@ -27,15 +44,14 @@ impl Iterator for StdioLinesIterator {
     type Item = String;
     fn next(&mut self) -> Option<Self::Item> {
         let mut s = String::new();
-        if self.stdin.read_line(&mut s).is_err() {
-            return None;
+        match self.stdin.read_line(&mut s) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(s),
         }
-        Some(s)
     }
 }
 
 fn main_loop<T: Iterator<Item = String>>(mut lines: T, repl: bool) {
-    let mut env: HashMap<String, Value> = HashMap::new();
     let mut stdout = std::io::stdout();
     loop {
         if repl {
@@ -47,30 +63,34 @@ fn main_loop<T: Iterator<Item = String>>(mut lines: T, repl: bool) {
             Some(s) => input = s,
             None => break,
         }
-        loop {
-            let mut parens = 0;
-            for ch in input.chars() {
-                match ch {
-                    '(' => parens += 1,
-                    ')' => parens -= 1,
-                    _ => (),
+        // An input cut off by EOF is left for the reader to report
+        while is_incomplete(&input) {
+            match lines.next() {
+                Some(line) => {
+                    input.push('\n');
+                    input.push_str(&line);
                 }
+                None => break,
             }
-            if parens == 0 {
-                break;
-            }
-            input.push_str(lines.next().expect("Unexpected EOF").as_str());
         }
 
         if input.trim() == "env" {
-            println!("{:#?}", env);
+            println!("{:#?}", environments::program().borrow());
             continue;
         } else if input.trim() == "exit" {
             return;
         }
 
-        let mut tokens = tokenize(&input);
-        let parsed = parse_expression(&mut tokens);
+        let tokens = tokenize(&input);
+        // Nothing but comments
+        if let Ok(tokens) = &tokens {
+            if tokens.get_state().is_none() {
+                continue;
+            }
+        }
+        let parsed = tokens
+            .map_err(Exception::from)
+            .and_then(|mut tokens| parse_expression(&mut tokens));
         if let Err(msg) = parsed {
             println!("{}", msg);
             if repl {
@@ -82,13 +102,13 @@ fn main_loop<T: Iterator<Item = String>>(mut lines: T, repl: bool) {
         }
         let parsed = parsed.unwrap();
         if repl {
-            match eval_expression(&parsed, &mut env) {
+            match eval_top_level(&parsed) {
                 Ok(Value::Nil) => (),
                 Ok(v) => println!("{}", v),
                 Err(msg) => println!("{}", msg),
             }
         } else {
-            match eval_expression(&parsed, &mut env) {
+            match eval_top_level(&parsed) {
                 Ok(_) => (),
                 Err(msg) => {
                     eprintln!("ERROR!!!: {}", msg);
@@ -100,18 +120,49 @@ fn main_loop<T: Iterator<Item = String>>(mut lines: T, repl: bool) {
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1 {
-        let filename = &args[1];
-        let contents = std::fs::read_to_string(filename)
-            .unwrap_or_else(|_| panic!("Couldn't read file {}", filename));
-        main_loop(
-            contents
-                .lines()
-                .map(|s| s.to_string())
-                .filter(|s| !s.is_empty()),
-            false,
-        );
+    ports::set_current_output_port(Box::new(std::io::stdout()));
+    ports::set_current_input_port(Box::new(ports::SharedStdin::default()));
+    let mut filename = None;
+    let mut use_prelude = true;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-L" | "--lib-path" => match args.next() {
+                Some(dir) => libraries::add_search_path(dir.into()),
+                None => {
+                    eprintln!("Expected a directory after {}", arg);
+                    return;
+                }
+            },
+            "--no-prelude" => use_prelude = false,
+            _ => filename = Some(arg),
+        }
+    }
+    // Directories given on the command line are searched before those in the environment
+    if let Some(dirs) = std::env::var_os("LISP_LIBRARY_PATH") {
+        for dir in std::env::split_paths(&dirs) {
+            libraries::add_search_path(dir);
+        }
+    }
+    if use_prelude {
+        let prelude = prelude::environment().expect("Error evaluating the prelude!");
+        environments::program().borrow_mut().extend(prelude);
+    }
+    if let Some(filename) = filename {
+        // Loading the file makes it the one that `load` and `include` paths are relative to
+        let res = loader::with_file(&filename, |contents| {
+            main_loop(
+                contents
+                    .lines()
+                    .map(|s| s.to_string())
+                    .filter(|s| !s.is_empty()),
+                false,
+            );
+            Ok(())
+        });
+        if let Err(msg) = res {
+            eprintln!("ERROR!!!: {}", msg);
+        }
     } else {
         let lines = StdioLinesIterator::new();
         main_loop(lines, true);
@@ -0,0 +1,789 @@
+use crate::eval::{Builtin, Value};
+use crate::exceptions::Exception;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "+" => add,
+        "-" => subtract,
+        "*" => multiply,
+        "/" => divide,
+        "<" => |args, _| compare(args, Ordering::is_lt),
+        ">" => |args, _| compare(args, Ordering::is_gt),
+        "<=" => |args, _| compare(args, Ordering::is_le),
+        ">=" => |args, _| compare(args, Ordering::is_ge),
+        "even?" => |args, _| parity("even?", args, 0),
+        "odd?" => |args, _| parity("odd?", args, 1),
+        "max" => |args, _| extremum("max", args, true),
+        "min" => |args, _| extremum("min", args, false),
+        "=" => numeric_equal,
+        "int" => int,
+        "sqrt" => sqrt,
+        "exp" => exp,
+        "log" => log,
+        "make-rectangular" => make_rectangular,
+        "make-polar" => make_polar,
+        "real-part" => real_part,
+        "imag-part" => imag_part,
+        "magnitude" => magnitude,
+        "angle" => angle,
+        "bitwise-and" => bitwise_and,
+        "bitwise-or" => bitwise_or,
+        "bitwise-xor" => bitwise_xor,
+        "bitwise-not" => bitwise_not,
+        "arithmetic-shift" => arithmetic_shift,
+        "bit-count" => bit_count,
+        "integer-length" => integer_length,
+        "bit-set?" => bit_set,
+        "number->string" => number_to_string,
+        "string->number" => string_to_number,
+        "format-fixed" => format_fixed,
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// The radix selected by a `#x`, `#b`, `#o` or `#d` prefix on a number.
+pub fn radix_prefix(s: &str) -> Option<u32> {
+    match s.get(..2)? {
+        "#x" | "#X" => Some(16),
+        "#b" | "#B" => Some(2),
+        "#o" | "#O" => Some(8),
+        "#d" | "#D" => Some(10),
+        _ => None,
+    }
+}
+
+/// Parses numeric syntax in the given default radix, honouring any radix prefix.
+/// Only decimal numbers may be inexact or complex.
+pub fn parse_number(s: &str, radix: u32) -> Option<Value> {
+    let (s, radix) = match radix_prefix(s) {
+        Some(r) => (&s[2..], r),
+        None => (s, radix),
+    };
+    if radix == 10 {
+        if let Some((magnitude, angle)) = s.split_once('@') {
+            let magnitude = real_value(&parse_real(magnitude, radix)?)?;
+            let angle = real_value(&parse_real(angle, radix)?)?;
+            return Some(make_complex(
+                magnitude * angle.cos(),
+                magnitude * angle.sin(),
+            ));
+        } else if let Some(body) = s.strip_suffix('i') {
+            return parse_rectangular(body);
+        }
+    }
+    parse_real(s, radix)
+}
+
+/// Parses the `a+b` part of a complex literal `a+bi`, where `a` may be left out and `b` may
+/// be just a sign.
+fn parse_rectangular(body: &str) -> Option<Value> {
+    let split = body
+        .char_indices()
+        .rev()
+        .find(|&(i, c)| (c == '+' || c == '-') && !(i > 0 && body[..i].ends_with(['e', 'E'])))
+        .map(|(i, _)| i)?;
+    let re = if split == 0 {
+        0.0
+    } else {
+        real_value(&parse_real(&body[..split], 10)?)?
+    };
+    let im = match &body[split..] {
+        "+" => 1.0,
+        "-" => -1.0,
+        im => real_value(&parse_real(im, 10)?)?,
+    };
+    Some(make_complex(re, im))
+}
+
+fn parse_real(s: &str, radix: u32) -> Option<Value> {
+    match s {
+        "+inf.0" => return Some(Value::Number(f64::INFINITY)),
+        "-inf.0" => return Some(Value::Number(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Value::Number(f64::NAN)),
+        _ => (),
+    }
+    let digits = s.trim_start_matches(['+', '-']);
+    if digits.len() + 1 < s.len() || !digits.chars().any(|c| c.is_digit(radix)) {
+        return None;
+    }
+    if let Ok(n) = isize::from_str_radix(s, radix) {
+        Some(Value::Integer(n))
+    } else if radix == 10 && digits.chars().all(|c| "0123456789.eE+-".contains(c)) {
+        s.parse::<f64>().ok().map(Value::Number)
+    } else {
+        None
+    }
+}
+
+/// Builds a complex number, collapsing it to a real one when the imaginary part is zero.
+pub fn make_complex(re: f64, im: f64) -> Value {
+    if im == 0.0 {
+        Value::Number(re)
+    } else {
+        Value::Complex(re, im)
+    }
+}
+
+/// The shortest representation of `n` that reads back as the same float, always marked
+/// as inexact.
+pub fn format_float(n: f64) -> String {
+    if n.is_nan() {
+        "+nan.0".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "+inf.0" } else { "-inf.0" }.to_string()
+    } else {
+        format!("{:?}", n)
+    }
+}
+
+pub fn format_complex(re: f64, im: f64) -> String {
+    let sign = if im.is_nan() || im.is_infinite() || im.is_sign_negative() {
+        ""
+    } else {
+        "+"
+    };
+    format!("{}{}{}i", format_float(re), sign, format_float(im))
+}
+
+pub fn format_integer(n: isize, radix: u32) -> String {
+    let mut digits = Vec::new();
+    let mut m = n.unsigned_abs();
+    loop {
+        digits.push(std::char::from_digit((m % radix as usize) as u32, radix).unwrap());
+        m /= radix as usize;
+        if m == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+fn real_value(v: &Value) -> Option<f64> {
+    match v {
+        Value::Integer(n) => Some(*n as f64),
+        Value::Number(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// The real and imaginary parts of a complex number.
+type Complex = (f64, f64);
+
+fn complex_value(v: &Value) -> Option<Complex> {
+    match v {
+        Value::Complex(re, im) => Some((*re, *im)),
+        v => real_value(v).map(|n| (n, 0.0)),
+    }
+}
+
+/// Applies a binary operation at the lowest level of the numeric tower that both arguments
+/// fit in. `int` returns `None` when the result isn't an integer (e.g. on overflow), in which
+/// case the operation is retried on floats.
+fn arithmetic(
+    a: &Value,
+    b: &Value,
+    int: fn(isize, isize) -> Option<isize>,
+    real: fn(f64, f64) -> f64,
+    complex: fn(Complex, Complex) -> Complex,
+) -> Result<Value, Exception> {
+    if let (Value::Integer(x), Value::Integer(y)) = (a, b) {
+        if let Some(n) = int(*x, *y) {
+            return Ok(Value::Integer(n));
+        }
+    }
+    let x = complex_value(a).ok_or_else(|| format!("{:#?} is not a number!", a))?;
+    let y = complex_value(b).ok_or_else(|| format!("{:#?} is not a number!", b))?;
+    if let (Value::Complex(_, _), _) | (_, Value::Complex(_, _)) = (a, b) {
+        let (re, im) = complex(x, y);
+        Ok(make_complex(re, im))
+    } else {
+        Ok(Value::Number(real(x.0, y.0)))
+    }
+}
+
+fn add2(a: &Value, b: &Value) -> Result<Value, Exception> {
+    arithmetic(
+        a,
+        b,
+        |x, y| x.checked_add(y),
+        |x, y| x + y,
+        |(a, b), (c, d)| (a + c, b + d),
+    )
+}
+
+fn subtract2(a: &Value, b: &Value) -> Result<Value, Exception> {
+    arithmetic(
+        a,
+        b,
+        |x, y| x.checked_sub(y),
+        |x, y| x - y,
+        |(a, b), (c, d)| (a - c, b - d),
+    )
+}
+
+fn multiply2(a: &Value, b: &Value) -> Result<Value, Exception> {
+    arithmetic(
+        a,
+        b,
+        |x, y| x.checked_mul(y),
+        |x, y| x * y,
+        |(a, b), (c, d)| (a * c - b * d, a * d + b * c),
+    )
+}
+
+fn divide2(a: &Value, b: &Value) -> Result<Value, Exception> {
+    arithmetic(
+        a,
+        b,
+        |_, _| None,
+        |x, y| x / y,
+        |(a, b), (c, d)| {
+            let denominator = c * c + d * d;
+            ((a * c + b * d) / denominator, (b * c - a * d) / denominator)
+        },
+    )
+}
+
+fn add(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    args.iter()
+        .try_fold(Value::Integer(0), |acc, x| add2(&acc, x))
+}
+
+fn multiply(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    args.iter()
+        .try_fold(Value::Integer(1), |acc, x| multiply2(&acc, x))
+}
+
+/// Subtracts the rest of its arguments from the first, or negates a single argument.
+fn subtract(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [] => Err("Expected at least one argument to `-`".into()),
+        [x] => subtract2(&Value::Integer(0), x),
+        [x, rest @ ..] => rest.iter().try_fold(x.clone(), |acc, y| subtract2(&acc, y)),
+    }
+}
+
+/// Divides the first argument by the rest, or takes the reciprocal of a single argument.
+/// Real division always produces a float.
+fn divide(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [] => Err("Expected at least one argument to `/`".into()),
+        [x] => divide2(&Value::Integer(1), x),
+        [x, rest @ ..] => rest.iter().try_fold(x.clone(), |acc, y| divide2(&acc, y)),
+    }
+}
+
+/// True if each of the real arguments is `ordered` against the next one, so `<` is true for
+/// strictly increasing arguments.
+fn compare(args: &[Value], ordered: fn(Ordering) -> bool) -> Result<Value, Exception> {
+    let ns = args
+        .iter()
+        .map(|v| match v {
+            Value::Integer(n) => Ok(Value::Integer(*n)),
+            v => real_value(v)
+                .map(Value::Number)
+                .ok_or_else(|| format!("{:#?} not a real number!", v).into()),
+        })
+        .collect::<Result<Vec<Value>, Exception>>()?;
+    Ok(Value::Bool(ns.windows(2).all(|w| {
+        let order = match (&w[0], &w[1]) {
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            (a, b) => real_value(a).partial_cmp(&real_value(b)),
+        };
+        order.is_some_and(ordered)
+    })))
+}
+
+/// True if the integer argument leaves `remainder` when divided by 2. Floats with no
+/// fractional part count as integers.
+fn parity(name: &str, args: &[Value], remainder: isize) -> Result<Value, Exception> {
+    match args {
+        [Value::Integer(n)] => Ok(Value::Bool(n.rem_euclid(2) == remainder)),
+        [Value::Number(n)] if n.fract() == 0.0 => {
+            Ok(Value::Bool(n.rem_euclid(2.0) == remainder as f64))
+        }
+        _ => Err(format!("Expected an integer to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+/// The largest of its real arguments if `largest`, or else the smallest. The result is a float
+/// if any of the arguments is.
+fn extremum(name: &str, args: &[Value], largest: bool) -> Result<Value, Exception> {
+    let less = |a: &Value, b: &Value| match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a < b,
+        (a, b) => real_value(a) < real_value(b),
+    };
+    let mut res = match args.first() {
+        Some(v) => v,
+        None => return Err(format!("Expected at least one argument to `{}`", name).into()),
+    };
+    for v in args {
+        if !matches!(v, Value::Integer(_) | Value::Number(_)) {
+            return Err(format!("{:#?} not a real number!", v).into());
+        }
+        if (largest && less(res, v)) || (!largest && less(v, res)) {
+            res = v;
+        }
+    }
+    match res {
+        Value::Integer(n) if args.iter().any(|v| matches!(v, Value::Number(_))) => {
+            Ok(Value::Number(*n as f64))
+        }
+        v => Ok(v.clone()),
+    }
+}
+
+/// True if all of the arguments are numerically equal. Non-numbers are never equal.
+fn numeric_equal(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    Ok(Value::Bool(args.iter().skip(1).all(|v| {
+        match (v, &args[0]) {
+            (Value::Integer(n), Value::Integer(n2)) => n == n2,
+            (a, b) => match (complex_value(a), complex_value(b)) {
+                (Some(x), Some(y)) => x == y,
+                _ => false,
+            },
+        }
+    })))
+}
+
+/// True if all of the arguments are integers or floats with no fractional part.
+fn int(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    Ok(Value::Bool(args.iter().all(|x| match x {
+        Value::Number(n) => (*n - n.floor()).abs() < f64::EPSILON,
+        Value::Integer(_) => true,
+        _ => false,
+    })))
+}
+
+fn one_number<'a>(name: &str, args: &'a [Value]) -> Result<&'a Value, Exception> {
+    match args {
+        [v @ (Value::Integer(_) | Value::Number(_) | Value::Complex(_, _))] => Ok(v),
+        _ => Err(format!("Expected one number to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+/// The principal square root. Perfect squares stay exact, and negative numbers have
+/// imaginary roots.
+fn sqrt(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match one_number("sqrt", args)? {
+        Value::Integer(n) if *n >= 0 => {
+            let root = (*n as f64).sqrt().round() as isize;
+            if root.checked_mul(root) == Some(*n) {
+                Ok(Value::Integer(root))
+            } else {
+                Ok(Value::Number((*n as f64).sqrt()))
+            }
+        }
+        Value::Complex(re, im) => {
+            let r = re.hypot(*im);
+            let root_im = ((r - re) / 2.0).sqrt();
+            Ok(make_complex(
+                ((r + re) / 2.0).sqrt(),
+                if im.is_sign_negative() {
+                    -root_im
+                } else {
+                    root_im
+                },
+            ))
+        }
+        v => {
+            let n = real_value(v).unwrap();
+            if n < 0.0 {
+                Ok(Value::Complex(0.0, (-n).sqrt()))
+            } else {
+                Ok(Value::Number(n.sqrt()))
+            }
+        }
+    }
+}
+
+fn exp(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match one_number("exp", args)? {
+        Value::Complex(re, im) => {
+            let magnitude = re.exp();
+            Ok(make_complex(magnitude * im.cos(), magnitude * im.sin()))
+        }
+        v => Ok(Value::Number(real_value(v).unwrap().exp())),
+    }
+}
+
+fn complex_log(v: &Value) -> Value {
+    match v {
+        Value::Complex(re, im) => make_complex(re.hypot(*im).ln(), im.atan2(*re)),
+        v => {
+            let n = real_value(v).unwrap();
+            if n < 0.0 {
+                Value::Complex((-n).ln(), std::f64::consts::PI)
+            } else {
+                Value::Number(n.ln())
+            }
+        }
+    }
+}
+
+/// The natural logarithm, or `(log z base)` for another base. Negative and complex arguments
+/// give complex results.
+fn log(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [z, base] => {
+            let z = complex_log(one_number("log", std::slice::from_ref(z))?);
+            let base = complex_log(one_number("log", std::slice::from_ref(base))?);
+            divide2(&z, &base)
+        }
+        _ => Ok(complex_log(one_number("log", args)?)),
+    }
+}
+
+fn make_rectangular(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [re, im] => match (real_value(re), real_value(im)) {
+            (Some(re), Some(im)) => Ok(make_complex(re, im)),
+            _ => Err(format!(
+                "Expected real arguments to `make-rectangular`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        _ => Err(format!(
+            "Expected two arguments to `make-rectangular`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn make_polar(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [magnitude, angle] => match (real_value(magnitude), real_value(angle)) {
+            (Some(m), Some(a)) => Ok(make_complex(m * a.cos(), m * a.sin())),
+            _ => Err(format!("Expected real arguments to `make-polar`, got {:#?}", args).into()),
+        },
+        _ => Err(format!("Expected two arguments to `make-polar`, got {:#?}", args).into()),
+    }
+}
+
+fn real_part(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match one_number("real-part", args)? {
+        Value::Complex(re, _) => Ok(Value::Number(*re)),
+        v => Ok(v.clone()),
+    }
+}
+
+fn imag_part(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match one_number("imag-part", args)? {
+        Value::Complex(_, im) => Ok(Value::Number(*im)),
+        _ => Ok(Value::Integer(0)),
+    }
+}
+
+fn magnitude(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match one_number("magnitude", args)? {
+        Value::Integer(n) => n
+            .checked_abs()
+            .map(Value::Integer)
+            .ok_or_else(|| format!("Integer overflow in `magnitude` of {}", n).into()),
+        Value::Complex(re, im) => Ok(Value::Number(re.hypot(*im))),
+        v => Ok(Value::Number(real_value(v).unwrap().abs())),
+    }
+}
+
+fn angle(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match one_number("angle", args)? {
+        Value::Complex(re, im) => Ok(Value::Number(im.atan2(*re))),
+        v if real_value(v).unwrap() < 0.0 => Ok(Value::Number(std::f64::consts::PI)),
+        Value::Integer(_) => Ok(Value::Integer(0)),
+        _ => Ok(Value::Number(0.0)),
+    }
+}
+
+fn integer_args(name: &str, args: &[Value]) -> Result<Vec<isize>, Exception> {
+    args.iter()
+        .map(|v| match v {
+            Value::Integer(n) => Ok(*n),
+            v => Err(format!("Expected integer argument to `{}`, got {:#?}", name, v).into()),
+        })
+        .collect()
+}
+
+fn bitwise_and(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let ns = integer_args("bitwise-and", args)?;
+    Ok(Value::Integer(ns.iter().fold(-1, |acc, n| acc & n)))
+}
+
+fn bitwise_or(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let ns = integer_args("bitwise-or", args)?;
+    Ok(Value::Integer(ns.iter().fold(0, |acc, n| acc | n)))
+}
+
+fn bitwise_xor(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let ns = integer_args("bitwise-xor", args)?;
+    Ok(Value::Integer(ns.iter().fold(0, |acc, n| acc ^ n)))
+}
+
+fn bitwise_not(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match integer_args("bitwise-not", args)?.as_slice() {
+        [n] => Ok(Value::Integer(!n)),
+        _ => Err(format!("Expected one argument to `bitwise-not`, got {:#?}", args).into()),
+    }
+}
+
+/// Shifts left for positive counts and right (sign-extending) for negative ones.
+fn arithmetic_shift(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (n, count) = match integer_args("arithmetic-shift", args)?.as_slice() {
+        [n, count] => (*n, *count),
+        _ => {
+            return Err(format!(
+                "Expected two arguments to `arithmetic-shift`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    let bits = isize::BITS as isize;
+    if count >= 0 {
+        if n == 0 {
+            return Ok(Value::Integer(0));
+        }
+        let shifted = if count < bits { n << count } else { 0 };
+        if count >= bits || shifted >> count != n {
+            return Err(format!(
+                "Integer overflow in `arithmetic-shift` of {} by {}",
+                n, count
+            )
+            .into());
+        }
+        Ok(Value::Integer(shifted))
+    } else {
+        // `count` may be as low as `isize::MIN`, which has no positive counterpart
+        let count = count.unsigned_abs().min(isize::BITS as usize - 1);
+        Ok(Value::Integer(n >> count))
+    }
+}
+
+/// Counts the 1 bits of a non-negative integer, or the 0 bits of a negative one.
+fn bit_count(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match integer_args("bit-count", args)?.as_slice() {
+        [n] if *n < 0 => Ok(Value::Integer(n.count_zeros() as isize)),
+        [n] => Ok(Value::Integer(n.count_ones() as isize)),
+        _ => Err(format!("Expected one argument to `bit-count`, got {:#?}", args).into()),
+    }
+}
+
+/// The number of bits needed to represent an integer, not counting the sign bit.
+fn integer_length(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match integer_args("integer-length", args)?.as_slice() {
+        [n] => {
+            let n = if *n < 0 { !*n } else { *n };
+            Ok(Value::Integer((isize::BITS - n.leading_zeros()) as isize))
+        }
+        _ => Err(format!("Expected one argument to `integer-length`, got {:#?}", args).into()),
+    }
+}
+
+/// `(bit-set? index n)` tests bit `index` of `n` in two's complement.
+fn bit_set(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match integer_args("bit-set?", args)?.as_slice() {
+        [index, _] if *index < 0 => {
+            Err(format!("Expected a non-negative index to `bit-set?`, got {}", index).into())
+        }
+        [index, n] => {
+            let index = (*index).min(isize::BITS as isize - 1);
+            Ok(Value::Bool((n >> index) & 1 == 1))
+        }
+        _ => Err(format!("Expected two arguments to `bit-set?`, got {:#?}", args).into()),
+    }
+}
+
+fn number_to_string(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let radix = match args.get(1) {
+        None => 10,
+        Some(Value::Integer(r)) if (2..=36).contains(r) => *r as u32,
+        Some(v) => return Err(format!("Invalid radix to `number->string`: {:#?}", v).into()),
+    };
+    match args {
+        [Value::Integer(n)] | [Value::Integer(n), _] => {
+            Ok(Value::String(format_integer(*n, radix)))
+        }
+        [Value::Number(n)] | [Value::Number(n), Value::Integer(10)] => {
+            Ok(Value::String(format_float(*n)))
+        }
+        [Value::Complex(re, im)] | [Value::Complex(re, im), Value::Integer(10)] => {
+            Ok(Value::String(format_complex(*re, *im)))
+        }
+        [Value::Number(_), _] | [Value::Complex(_, _), _] => Err(format!(
+            "Inexact numbers can only be written in radix 10, got radix {}",
+            radix
+        )
+        .into()),
+        _ => Err(format!(
+            "Expected a number and optional radix to `number->string`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// Parses a string as a number, returning `#f` if it isn't valid numeric syntax.
+fn string_to_number(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let radix = match args.get(1) {
+        None => 10,
+        Some(Value::Integer(r)) if (2..=36).contains(r) => *r as u32,
+        Some(v) => return Err(format!("Invalid radix to `string->number`: {:#?}", v).into()),
+    };
+    match args {
+        [Value::String(s)] | [Value::String(s), _] => {
+            Ok(parse_number(s, radix).unwrap_or(Value::Bool(false)))
+        }
+        _ => Err(format!(
+            "Expected a string and optional radix to `string->number`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(format-fixed x digits)` writes `x` with exactly `digits` digits after the decimal point.
+fn format_fixed(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (n, digits) = match args {
+        [Value::Integer(n), Value::Integer(d)] if *d >= 0 => (*n as f64, *d as usize),
+        [Value::Number(n), Value::Integer(d)] if *d >= 0 => (*n, *d as usize),
+        _ => {
+            return Err(format!(
+                "Expected a number and a non-negative precision to `format-fixed`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    if n.is_finite() {
+        Ok(Value::String(format!("{:.*}", digits, n)))
+    } else {
+        Ok(Value::String(format_float(n)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn arithmetic_shift() {
+        assert_eq!(run("(arithmetic-shift 1 3)"), Ok("8".into()));
+        assert_eq!(run("(arithmetic-shift -8 -2)"), Ok("-2".into()));
+        assert_eq!(
+            run("(arithmetic-shift 1 -9223372036854775808)"),
+            Ok("0".into())
+        );
+        assert_eq!(
+            run("(arithmetic-shift -5 -9223372036854775808)"),
+            Ok("-1".into())
+        );
+        assert!(run("(arithmetic-shift 1 64)").is_err());
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        assert_eq!(
+            run("(list (bitwise-and #b1100 #b1010) (bitwise-or 12 10) (bitwise-xor 12 10))"),
+            Ok("(8 14 6)".into())
+        );
+        assert_eq!(
+            run("(list (bitwise-not 0) (bitwise-and) (bitwise-or))"),
+            Ok("(-1 -1 0)".into())
+        );
+        assert_eq!(
+            run("(list (bit-count 255) (bit-count -1) (integer-length 255) (integer-length -256))"),
+            Ok("(8 0 8 8)".into())
+        );
+        assert_eq!(
+            run("(list (bit-set? 3 8) (bit-set? 64 1))"),
+            Ok("(#t #f)".into())
+        );
+        assert!(run("(bitwise-and 1.0 2)").is_err());
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(
+            run("(list (< 1 2 3) (< 1 3 2) (< 3))"),
+            Ok("(#t #f #t)".into())
+        );
+        assert_eq!(
+            run("(list (> 3 2 1) (> 3 3 1) (>= 3 3 1) (<= 1 1 2) (<= 2 1))"),
+            Ok("(#t #f #t #t #f)".into())
+        );
+        assert_eq!(run("(apply > '(5 4 3 2))"), Ok("#t".into()));
+        assert_eq!(run("(list (< 1 1.5 2) (>= 2.0 2))"), Ok("(#t #t)".into()));
+        assert!(run("(< 1 'a)").is_err());
+    }
+
+    #[test]
+    fn max_and_min() {
+        assert_eq!(
+            run("(list (max 1 7 3) (min 4 2 8) (max 3))"),
+            Ok("(7 2 3)".into())
+        );
+        assert_eq!(
+            run("(list (max 1 2.0) (min 1 0.5))"),
+            Ok("(2.0 0.5)".into())
+        );
+        assert_eq!(run("(apply max '(4 9 2))"), Ok("9".into()));
+        assert!(run("(max)").is_err());
+    }
+
+    #[test]
+    fn parity() {
+        assert_eq!(
+            run("(list (even? 4) (odd? 4) (even? -3) (odd? -3) (even? 0))"),
+            Ok("(#t #f #f #t #t)".into())
+        );
+        assert_eq!(
+            run("(list (odd? 9007199254740993) (even? 9007199254740992))"),
+            Ok("(#t #t)".into())
+        );
+        assert_eq!(run("(even? 6.0)"), Ok("#t".into()));
+        assert!(run("(even? 1.5)").is_err());
+    }
+
+    #[test]
+    fn number_conversions() {
+        assert_eq!(
+            run("(list (number->string 255 16) (number->string -10 2) (number->string 2.0))"),
+            Ok("(\"ff\" \"-1010\" \"2.0\")".into())
+        );
+        assert_eq!(
+            run("(list (string->number \"ff\" 16) (string->number \"1e3\") (string->number \"#xff\"))"),
+            Ok("(255 1000.0 255)".into())
+        );
+        assert_eq!(run("(string->number \"abc\")"), Ok("#f".into()));
+        assert_eq!(
+            run("(list (format-fixed 3.14159 2) (format-fixed 2 3))"),
+            Ok("(\"3.14\" \"2.000\")".into())
+        );
+        assert!(run("(number->string 10 37)").is_err());
+        assert!(run("(number->string 1.5 2)").is_err());
+    }
+
+    #[test]
+    fn complex_numbers() {
+        assert_eq!(run("(* 1+2i 1-2i)"), Ok("5.0".into()));
+        assert_eq!(run("(sqrt -4)"), Ok("0.0+2.0i".into()));
+        assert_eq!(
+            run("(list (sqrt 16) (sqrt 2))"),
+            Ok("(4 1.4142135623730951)".into())
+        );
+        assert_eq!(
+            run("(list (make-rectangular 1 2) (real-part 3+4i) (imag-part 3+4i) (magnitude 3+4i))"),
+            Ok("(1.0+2.0i 3.0 4.0 5.0)".into())
+        );
+        assert_eq!(run("(list (+ 1+i 1) -i)"), Ok("(2.0+1.0i 0.0-1.0i)".into()));
+        assert_eq!(run("(list (complex? 1) (real? 1+i))"), Ok("(#t #f)".into()));
+        assert!(run("(< 1+i 2)").is_err());
+    }
+}
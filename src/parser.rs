@@ -1,22 +1,38 @@
-use crate::tokenizer::{TokenIterator, TokenType};
+use crate::bytevectors;
+use crate::eval::{cons, list_to_vec, Value};
+use crate::exceptions::{Exception, Guard};
+use crate::libraries::{ImportSet, LibrarySpec};
+use crate::loader;
+use crate::records::RecordSpec;
+use crate::tokenizer::{tokenize, TokenIterator, TokenType};
+use crate::vectors;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug)]
 pub enum Expression {
     Number(f64),
     Integer(isize),
+    Complex(f64, f64),
     Identifier(String),
     SExpression(Box<Expression>, Vec<Expression>),
     Lambda(Vec<String>, Box<Expression>),
     Define(String, Box<Expression>),
     If(Box<Expression>, Box<Expression>, Box<Expression>),
     Bool(bool),
-    Pair(Box<Expression>, Box<Expression>),
     Char(char),
     String(String),
+    /// A value that has already been evaluated, such as one captured by a lambda.
+    Literal(Box<Value>),
+    DefineRecordType(RecordSpec),
+    DefineLibrary(LibrarySpec),
+    Import(Vec<ImportSet>),
+    Guard(Box<Guard>),
+    /// Forms evaluated in order, like those spliced in by `include`. The last gives the value.
+    Sequence(Vec<Expression>),
     Nil,
 }
 
-pub fn parse_expression(current: &mut TokenIterator) -> Result<Expression, String> {
+pub fn parse_expression(current: &mut TokenIterator) -> Result<Expression, Exception> {
     match &current.get_state().ok_or("Invalid state!") {
         Ok(TokenType::OpenParen) => {
             let next = current.next().ok_or("Unexpected EOF!");
@@ -29,6 +45,10 @@ pub fn parse_expression(current: &mut TokenIterator) -> Result<Expression, Strin
                     let if_branch = parse_expression(current)?;
                     current.next();
                     let else_branch = parse_expression(current)?;
+                    if let Some(TokenType::CloseParen) = current.next() {
+                    } else {
+                        return Err("Expected ')' after else branch of if!".into());
+                    }
                     Ok(Expression::If(
                         Box::new(cond),
                         Box::new(if_branch),
@@ -39,22 +59,65 @@ pub fn parse_expression(current: &mut TokenIterator) -> Result<Expression, Strin
                     if let Ok(TokenType::Identifier(s)) = current.next().ok_or("Unexpected EOF!") {
                         current.next();
                         let expr = parse_expression(current)?;
+                        if let Some(TokenType::CloseParen) = current.next() {
+                        } else {
+                            return Err("Expected ')' after define!".into());
+                        }
                         Ok(Expression::Define(s, Box::new(expr)))
                     } else {
-                        Err(format!("Expected identifier after define!"))
+                        Err("Expected identifier after define!".into())
+                    }
+                }
+                Ok(TokenType::Identifier(s)) if s == "quote" => {
+                    current.next().ok_or("Unexpected EOF!")?;
+                    let datum = parse_datum(current)?;
+                    if let Some(TokenType::CloseParen) = current.next() {
+                    } else {
+                        return Err("Expected ')' after quote!".into());
+                    }
+                    Ok(Expression::Literal(Box::new(datum)))
+                }
+                Ok(TokenType::Identifier(s)) if s == "define-record-type" => Ok(
+                    Expression::DefineRecordType(RecordSpec::new(&parse_data(current)?)?),
+                ),
+                Ok(TokenType::Identifier(s)) if s == "define-library" => Ok(
+                    Expression::DefineLibrary(LibrarySpec::new(&parse_data(current)?)?),
+                ),
+                Ok(TokenType::Identifier(s)) if s == "guard" => Ok(Expression::Guard(Box::new(
+                    Guard::new(&parse_data(current)?)?,
+                ))),
+                Ok(TokenType::Identifier(s)) if s == "import" => Ok(Expression::Import(
+                    parse_data(current)?
+                        .iter()
+                        .map(ImportSet::new)
+                        .collect::<Result<Vec<ImportSet>, Exception>>()?,
+                )),
+                Ok(TokenType::Identifier(s)) if s == "include" => {
+                    let mut paths = Vec::new();
+                    loop {
+                        match current.next().ok_or("Unexpected EOF!")? {
+                            TokenType::CloseParen => break,
+                            TokenType::String(path) => paths.push(Value::String(path)),
+                            t => {
+                                return Err(
+                                    format!("Expected a file name to include, got {:?}", t).into()
+                                )
+                            }
+                        }
                     }
+                    include(&paths)
                 }
                 Ok(TokenType::Lambda) => {
                     if let Ok(TokenType::OpenParen) = current.next().ok_or("Unexpected EOF!") {
                     } else {
-                        return Err(format!("Expected '(' after 'lambda'!"));
+                        return Err("Expected '(' after 'lambda'!".into());
                     }
                     let mut args = Vec::new();
                     loop {
                         match current.next().ok_or("Unexpected EOF!")? {
                             TokenType::Identifier(s) => args.push(s),
                             TokenType::CloseParen => break,
-                            t => return Err(format!("Invalid token {:?}!", t)),
+                            t => return Err(format!("Invalid token {:?}!", t).into()),
                         }
                     }
                     current.next();
@@ -78,37 +141,223 @@ pub fn parse_expression(current: &mut TokenIterator) -> Result<Expression, Strin
                 }
             }
         }
-        Ok(TokenType::CloseParen) => Err(format!("Unexpected ')'!")),
+        Ok(TokenType::VectorOpen) | Ok(TokenType::BytevectorOpen) => {
+            Ok(Expression::Literal(Box::new(parse_datum(current)?)))
+        }
+        Ok(TokenType::CloseParen) => Err("Unexpected ')'!".into()),
         Ok(TokenType::Identifier(s)) => Ok(Expression::Identifier(s.to_string())),
         Ok(TokenType::Integer(n)) => Ok(Expression::Integer(*n)),
+        Ok(TokenType::Complex(re, im)) => Ok(Expression::Complex(*re, *im)),
         Ok(TokenType::Number(n)) => Ok(Expression::Number(*n)),
-        Ok(TokenType::Lambda) => Err(format!("Lambda not expected in this position!")),
-        Ok(TokenType::Define) => Err(format!("Define not expected in this position!")),
-        Ok(TokenType::If) => Err(format!("If not expected in this position!")),
+        Ok(TokenType::Lambda) => Err("Lambda not expected in this position!".into()),
+        Ok(TokenType::Define) => Err("Define not expected in this position!".into()),
+        Ok(TokenType::If) => Err("If not expected in this position!".into()),
         Ok(TokenType::True) => Ok(Expression::Bool(true)),
         Ok(TokenType::False) => Ok(Expression::Bool(false)),
         Ok(TokenType::Char(c)) => Ok(Expression::Char(*c)),
-        Ok(TokenType::String(s)) => Ok(Expression::String(s[1..s.len() - 1].to_string())),
+        Ok(TokenType::String(s)) => Ok(Expression::String(s.clone())),
         Ok(TokenType::SingleQuote) => {
-            if let Some(TokenType::OpenParen) = current.next() {
-                let mut vals = Vec::new();
-                loop {
-                    if let Some(TokenType::CloseParen) = current.next() {
-                        break;
-                    } else {
-                        vals.push(parse_expression(current)?);
+            current.next().ok_or("Unexpected EOF!")?;
+            Ok(Expression::Literal(Box::new(parse_datum(current)?)))
+        }
+        Err(_) => Err("Error!".into()),
+    }
+}
+
+/// Reads the rest of a form as data, up to and including its closing parenthesis.
+fn parse_data(current: &mut TokenIterator) -> Result<Vec<Value>, Exception> {
+    let mut parts = Vec::new();
+    loop {
+        match current.next().ok_or("Unexpected EOF!")? {
+            TokenType::CloseParen => return Ok(parts),
+            _ => parts.push(parse_datum(current)?),
+        }
+    }
+}
+
+/// Reads the current token and those following it as data rather than code, the way `quote`
+/// sees them: identifiers become symbols and parentheses make lists.
+pub fn parse_datum(current: &mut TokenIterator) -> Result<Value, Exception> {
+    match current.get_state().ok_or("Unexpected EOF!")? {
+        TokenType::OpenParen => {
+            let mut elements = Vec::new();
+            let mut tail = Value::Nil;
+            loop {
+                match current.next().ok_or("Unexpected EOF!")? {
+                    TokenType::CloseParen => break,
+                    TokenType::Identifier(s) if s == "." && !elements.is_empty() => {
+                        current.next().ok_or("Unexpected EOF!")?;
+                        tail = parse_datum(current)?;
+                        if let Some(TokenType::CloseParen) = current.next() {
+                            break;
+                        }
+                        return Err("Expected ')' after the tail of a dotted list!".into());
                     }
+                    _ => elements.push(parse_datum(current)?),
                 }
-                vals.iter().rfold(Ok(Expression::Nil), |acc, x| {
-                    Ok(Expression::Pair(
-                        Box::new(x.clone()),
-                        Box::new(acc.unwrap()),
-                    ))
+            }
+            Ok(elements.into_iter().rfold(tail, |acc, x| cons(x, acc)))
+        }
+        TokenType::VectorOpen => {
+            let mut elements = Vec::new();
+            loop {
+                match current.next().ok_or("Unexpected EOF!")? {
+                    TokenType::CloseParen => break,
+                    _ => elements.push(parse_datum(current)?),
+                }
+            }
+            Ok(vectors::new_vector(elements))
+        }
+        TokenType::BytevectorOpen => {
+            let mut bytes = Vec::new();
+            loop {
+                match current.next().ok_or("Unexpected EOF!")? {
+                    TokenType::CloseParen => break,
+                    TokenType::Integer(n) if (0..=255).contains(&n) => bytes.push(n as u8),
+                    t => return Err(format!("Invalid byte {:?} in bytevector!", t).into()),
+                }
+            }
+            Ok(bytevectors::new_bytevector(bytes))
+        }
+        TokenType::SingleQuote => {
+            current.next().ok_or("Unexpected EOF!")?;
+            let datum = parse_datum(current)?;
+            Ok(cons(
+                Value::Symbol("quote".to_string()),
+                cons(datum, Value::Nil),
+            ))
+        }
+        TokenType::CloseParen => Err("Unexpected ')'!".into()),
+        TokenType::Lambda => Ok(Value::Symbol("lambda".to_string())),
+        TokenType::Define => Ok(Value::Symbol("define".to_string())),
+        TokenType::If => Ok(Value::Symbol("if".to_string())),
+        TokenType::True => Ok(Value::Bool(true)),
+        TokenType::False => Ok(Value::Bool(false)),
+        TokenType::Identifier(s) => Ok(Value::Symbol(s)),
+        TokenType::Integer(n) => Ok(Value::Integer(n)),
+        TokenType::Number(n) => Ok(Value::Number(n)),
+        TokenType::Complex(re, im) => Ok(Value::Complex(re, im)),
+        TokenType::Char(c) => Ok(Value::Char(c)),
+        TokenType::String(s) => Ok(Value::String(s)),
+    }
+}
+
+/// Turns data back into code, the way `eval` sees it: symbols become identifiers and lists
+/// become special forms or calls. Other data, like vectors, evaluate to themselves.
+pub fn datum_to_expression(datum: &Value) -> Result<Expression, Exception> {
+    let parts = match datum {
+        Value::Symbol(s) => return Ok(Expression::Identifier(s.clone())),
+        Value::Integer(n) => return Ok(Expression::Integer(*n)),
+        Value::Number(n) => return Ok(Expression::Number(*n)),
+        Value::Complex(re, im) => return Ok(Expression::Complex(*re, *im)),
+        Value::Bool(b) => return Ok(Expression::Bool(*b)),
+        Value::Char(c) => return Ok(Expression::Char(*c)),
+        Value::String(s) => return Ok(Expression::String(s.clone())),
+        Value::Nil => return Ok(Expression::Nil),
+        Value::Pair(_) => list_to_vec(datum)
+            .ok_or_else(|| format!("Expected a proper list to evaluate, got {}", datum))?,
+        v => return Ok(Expression::Literal(Box::new(v.clone()))),
+    };
+    let keyword = match &parts[0] {
+        Value::Symbol(s) => s.as_str(),
+        _ => "",
+    };
+    match (keyword, &parts[1..]) {
+        ("quote", [datum]) => Ok(Expression::Literal(Box::new(datum.clone()))),
+        ("if", [cond, if_branch, else_branch]) => Ok(Expression::If(
+            Box::new(datum_to_expression(cond)?),
+            Box::new(datum_to_expression(if_branch)?),
+            Box::new(datum_to_expression(else_branch)?),
+        )),
+        ("define", [Value::Symbol(name), expr]) => Ok(Expression::Define(
+            name.clone(),
+            Box::new(datum_to_expression(expr)?),
+        )),
+        ("lambda", [params, body]) => {
+            let params = list_to_vec(params)
+                .ok_or_else(|| format!("Expected a list of parameters in {}", datum))?
+                .iter()
+                .map(|param| match param {
+                    Value::Symbol(s) => Ok(s.clone()),
+                    v => Err(format!("Invalid parameter {} in {}", v, datum).into()),
                 })
-            } else {
-                Err(format!("Expected '(' after quote!"))
+                .collect::<Result<Vec<String>, Exception>>()?;
+            Ok(Expression::Lambda(
+                params,
+                Box::new(datum_to_expression(body)?),
+            ))
+        }
+        ("include", paths) => include(paths),
+        ("define-record-type", parts) => Ok(Expression::DefineRecordType(RecordSpec::new(parts)?)),
+        ("define-library", parts) => Ok(Expression::DefineLibrary(LibrarySpec::new(parts)?)),
+        ("guard", parts) => Ok(Expression::Guard(Box::new(Guard::new(parts)?))),
+        ("import", sets) => Ok(Expression::Import(
+            sets.iter()
+                .map(ImportSet::new)
+                .collect::<Result<Vec<ImportSet>, Exception>>()?,
+        )),
+        ("quote" | "if" | "define" | "lambda", _) => {
+            Err(format!("Invalid {} form {}", keyword, datum).into())
+        }
+        (_, args) => Ok(Expression::SExpression(
+            Box::new(datum_to_expression(&parts[0])?),
+            args.iter()
+                .map(datum_to_expression)
+                .collect::<Result<Vec<Expression>, Exception>>()?,
+        )),
+    }
+}
+
+/// Parses every form in `s`, as in a file.
+pub fn parse_all(s: &str) -> Result<Vec<Expression>, Exception> {
+    let mut tokens = tokenize(s)?;
+    let mut exprs = Vec::new();
+    if tokens.get_state().is_some() {
+        loop {
+            exprs.push(parse_expression(&mut tokens)?);
+            if tokens.next().is_none() {
+                break;
             }
         }
-        Err(_) => Err(format!("Error!")),
+    }
+    Ok(exprs)
+}
+
+/// The forms of the files named by `paths`, spliced together in place of an `include`.
+fn include(paths: &[Value]) -> Result<Expression, Exception> {
+    let mut exprs = Vec::new();
+    for path in paths {
+        match path {
+            Value::String(path) => exprs.extend(loader::with_file(path, parse_all)?),
+            v => return Err(format!("Expected a file name to include, got {}", v).into()),
+        }
+    }
+    Ok(Expression::Sequence(exprs))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn guard_clauses() {
+        assert_eq!(run("(guard (e) 1 2)"), Ok("2".into()));
+        assert_eq!(
+            run("(list (guard (e (else)) (raise 1)) (guard (e ((memv e (list 1 2)))) (raise 2)) (guard (e (1 2 3)) (raise 0)))"),
+            Ok("(#t (2) 3)".into())
+        );
+        assert_eq!(
+            run("(guard (e ((assq 'a e) => cdr) (else 'other)) (raise (list (cons 'a 42))))"),
+            Ok("42".into())
+        );
+        assert_eq!(
+            run("(guard e 1)"),
+            Err("Expected (var clause ...) in guard, got e".into())
+        );
+        assert_eq!(
+            run("(guard (e 5) 1)"),
+            Err("Invalid clause 5 in guard".into())
+        );
+        assert!(run("(guard (e (#t 1)))").is_err());
     }
 }
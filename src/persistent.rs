@@ -0,0 +1,672 @@
+use crate::eval::{cons, index_arg, list_to_vec, vec_to_list, Builtin, Value};
+use crate::exceptions::Exception;
+use crate::hashtables::HashKey;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "pmap" => |args, _| pmap_from_pairs("pmap", PMap::default(), args).map(Value::PMap),
+        "pset" => |args, _| pset_from(PMap::default(), args).map(Value::PSet),
+        "pvector" => |args, _| Ok(Value::PVector(pvector_from(PVector::default(), args))),
+        "alist->pmap" => alist_to_pmap,
+        "list->pset" => {
+            |args, _| pset_from(PMap::default(), &one_list("list->pset", args)?).map(Value::PSet)
+        }
+        "list->pvector" => |args, _| {
+            let elements = one_list("list->pvector", args)?;
+            Ok(Value::PVector(pvector_from(PVector::default(), &elements)))
+        },
+        "seq" => seq,
+        "pmap?" => |args, _| predicate("pmap?", args, |v| matches!(v, Value::PMap(_))),
+        "pset?" => |args, _| predicate("pset?", args, |v| matches!(v, Value::PSet(_))),
+        "pvector?" => |args, _| predicate("pvector?", args, |v| matches!(v, Value::PVector(_))),
+        "dissoc" => dissoc,
+        "conj" => conj,
+        "get" => get,
+        "contains?" => contains,
+        "count" => count,
+        _ => return None,
+    };
+    Some(f)
+}
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: u64 = WIDTH as u64 - 1;
+
+fn hash_of(key: &HashKey) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    hash_key: HashKey,
+    key: Value,
+    value: Value,
+}
+
+/// A node of a hash array mapped trie. Branches use 5 bits of the hash at each level to pick a
+/// child, storing only the children that exist. Leaves hold the entries whose keys share a
+/// whole hash, which is almost always just one.
+#[derive(Debug)]
+enum MapNode {
+    Branch(u32, Vec<Rc<MapNode>>),
+    Leaf(u64, Vec<Entry>),
+}
+
+impl MapNode {
+    fn get(&self, hash: u64, shift: u32, key: &HashKey) -> Option<&Entry> {
+        match self {
+            MapNode::Branch(bitmap, children) => {
+                let bit = 1 << ((hash >> shift) & MASK);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let i = (bitmap & (bit - 1)).count_ones() as usize;
+                children[i].get(hash, shift + BITS, key)
+            }
+            MapNode::Leaf(h, entries) if *h == hash => entries.iter().find(|e| e.hash_key == *key),
+            MapNode::Leaf(_, _) => None,
+        }
+    }
+
+    /// Returns a copy of the trie with `entry` added, sharing every untouched node, and
+    /// whether the entry was new rather than a replacement.
+    fn insert(node: &Rc<MapNode>, hash: u64, shift: u32, entry: Entry) -> (Rc<MapNode>, bool) {
+        match &**node {
+            MapNode::Branch(bitmap, children) => {
+                let bit = 1 << ((hash >> shift) & MASK);
+                let i = (bitmap & (bit - 1)).count_ones() as usize;
+                let mut children = children.clone();
+                if bitmap & bit == 0 {
+                    children.insert(i, Rc::new(MapNode::Leaf(hash, vec![entry])));
+                    (Rc::new(MapNode::Branch(bitmap | bit, children)), true)
+                } else {
+                    let (child, added) = MapNode::insert(&children[i], hash, shift + BITS, entry);
+                    children[i] = child;
+                    (Rc::new(MapNode::Branch(*bitmap, children)), added)
+                }
+            }
+            MapNode::Leaf(h, entries) if *h == hash => {
+                let mut entries = entries.clone();
+                let added = match entries.iter().position(|e| e.hash_key == entry.hash_key) {
+                    Some(i) => {
+                        entries[i] = entry;
+                        false
+                    }
+                    None => {
+                        entries.push(entry);
+                        true
+                    }
+                };
+                (Rc::new(MapNode::Leaf(hash, entries)), added)
+            }
+            MapNode::Leaf(h, _) => {
+                // Push the existing leaf down a level and try again from there
+                let bit = 1 << ((h >> shift) & MASK);
+                let branch = Rc::new(MapNode::Branch(bit, vec![node.clone()]));
+                MapNode::insert(&branch, hash, shift, entry)
+            }
+        }
+    }
+
+    /// Returns `None` if the key isn't present, or else the trie without it, which is `None`
+    /// itself if nothing is left.
+    fn remove(
+        node: &Rc<MapNode>,
+        hash: u64,
+        shift: u32,
+        key: &HashKey,
+    ) -> Option<Option<Rc<MapNode>>> {
+        match &**node {
+            MapNode::Branch(bitmap, children) => {
+                let bit = 1 << ((hash >> shift) & MASK);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let i = (bitmap & (bit - 1)).count_ones() as usize;
+                let mut children = children.clone();
+                match MapNode::remove(&children[i], hash, shift + BITS, key)? {
+                    Some(child) => {
+                        children[i] = child;
+                        Some(Some(Rc::new(MapNode::Branch(*bitmap, children))))
+                    }
+                    None => {
+                        children.remove(i);
+                        if children.is_empty() {
+                            Some(None)
+                        } else {
+                            Some(Some(Rc::new(MapNode::Branch(bitmap & !bit, children))))
+                        }
+                    }
+                }
+            }
+            MapNode::Leaf(h, entries) if *h == hash => {
+                let i = entries.iter().position(|e| e.hash_key == *key)?;
+                let mut entries = entries.clone();
+                entries.remove(i);
+                if entries.is_empty() {
+                    Some(None)
+                } else {
+                    Some(Some(Rc::new(MapNode::Leaf(hash, entries))))
+                }
+            }
+            MapNode::Leaf(_, _) => None,
+        }
+    }
+
+    fn collect<'a>(&'a self, res: &mut Vec<&'a Entry>) {
+        match self {
+            MapNode::Branch(_, children) => children.iter().for_each(|c| c.collect(res)),
+            MapNode::Leaf(_, entries) => res.extend(entries.iter()),
+        }
+    }
+}
+
+/// An immutable hash map. Updates return a new map sharing most of its structure with the
+/// old one. Sets are maps whose values are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct PMap {
+    root: Option<Rc<MapNode>>,
+    len: usize,
+}
+
+impl PMap {
+    /// Whether both maps share the same structure, so are certainly equal.
+    pub fn ptr_eq(&self, other: &PMap) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<Value>, Exception> {
+        let hash_key = HashKey::new(key)?;
+        Ok(self
+            .root
+            .as_ref()
+            .and_then(|root| root.get(hash_of(&hash_key), 0, &hash_key))
+            .map(|e| e.value.clone()))
+    }
+
+    pub fn insert(&self, key: Value, value: Value) -> Result<PMap, Exception> {
+        let hash_key = HashKey::new(&key)?;
+        let hash = hash_of(&hash_key);
+        let entry = Entry {
+            hash_key,
+            key,
+            value,
+        };
+        Ok(match &self.root {
+            Some(root) => {
+                let (root, added) = MapNode::insert(root, hash, 0, entry);
+                PMap {
+                    root: Some(root),
+                    len: self.len + added as usize,
+                }
+            }
+            None => PMap {
+                root: Some(Rc::new(MapNode::Leaf(hash, vec![entry]))),
+                len: 1,
+            },
+        })
+    }
+
+    pub fn remove(&self, key: &Value) -> Result<PMap, Exception> {
+        let hash_key = HashKey::new(key)?;
+        let removed = self
+            .root
+            .as_ref()
+            .and_then(|root| MapNode::remove(root, hash_of(&hash_key), 0, &hash_key));
+        Ok(match removed {
+            Some(root) => PMap {
+                root,
+                len: self.len - 1,
+            },
+            None => self.clone(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The keys and values, in an order fixed by the keys' hashes.
+    pub fn entries(&self) -> Vec<(Value, Value)> {
+        let mut res = Vec::new();
+        if let Some(root) = &self.root {
+            root.collect(&mut res);
+        }
+        res.into_iter()
+            .map(|e| (e.key.clone(), e.value.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+enum VectorNode {
+    Branch(Vec<Rc<VectorNode>>),
+    Leaf(Vec<Value>),
+}
+
+impl VectorNode {
+    /// A chain of nodes down to a leaf holding just `value`.
+    fn path(level: u32, value: Value) -> Rc<VectorNode> {
+        if level == 0 {
+            Rc::new(VectorNode::Leaf(vec![value]))
+        } else {
+            Rc::new(VectorNode::Branch(vec![VectorNode::path(
+                level - BITS,
+                value,
+            )]))
+        }
+    }
+
+    fn set(node: &VectorNode, level: u32, i: usize, value: Value) -> Rc<VectorNode> {
+        let slot = (i >> level) & (WIDTH - 1);
+        match node {
+            VectorNode::Branch(children) => {
+                let mut children = children.clone();
+                children[slot] = VectorNode::set(&children[slot], level - BITS, i, value);
+                Rc::new(VectorNode::Branch(children))
+            }
+            VectorNode::Leaf(values) => {
+                let mut values = values.clone();
+                values[slot] = value;
+                Rc::new(VectorNode::Leaf(values))
+            }
+        }
+    }
+
+    /// Appends `value` as element `i`, where the trie has room for it below this node.
+    fn push(node: &VectorNode, level: u32, i: usize, value: Value) -> Rc<VectorNode> {
+        match node {
+            VectorNode::Branch(children) => {
+                let slot = (i >> level) & (WIDTH - 1);
+                let mut children = children.clone();
+                if slot < children.len() {
+                    children[slot] = VectorNode::push(&children[slot], level - BITS, i, value);
+                } else {
+                    children.push(VectorNode::path(level - BITS, value));
+                }
+                Rc::new(VectorNode::Branch(children))
+            }
+            VectorNode::Leaf(values) => {
+                let mut values = values.clone();
+                values.push(value);
+                Rc::new(VectorNode::Leaf(values))
+            }
+        }
+    }
+
+    fn collect(&self, res: &mut Vec<Value>) {
+        match self {
+            VectorNode::Branch(children) => children.iter().for_each(|c| c.collect(res)),
+            VectorNode::Leaf(values) => res.extend(values.iter().cloned()),
+        }
+    }
+}
+
+/// An immutable vector, stored as a trie of 32-element nodes so that updates copy only the
+/// path to the changed element.
+#[derive(Clone, Debug)]
+pub struct PVector {
+    root: Rc<VectorNode>,
+    len: usize,
+    shift: u32,
+}
+
+impl Default for PVector {
+    fn default() -> PVector {
+        PVector {
+            root: Rc::new(VectorNode::Leaf(Vec::new())),
+            len: 0,
+            shift: 0,
+        }
+    }
+}
+
+impl PVector {
+    /// Whether both vectors share the same structure, so are certainly equal.
+    pub fn ptr_eq(&self, other: &PVector) -> bool {
+        Rc::ptr_eq(&self.root, &other.root) && self.len == other.len
+    }
+
+    pub fn get(&self, i: usize) -> Option<Value> {
+        if i >= self.len {
+            return None;
+        }
+        let mut node = &self.root;
+        let mut level = self.shift;
+        loop {
+            match &**node {
+                VectorNode::Branch(children) => {
+                    node = &children[(i >> level) & (WIDTH - 1)];
+                    level -= BITS;
+                }
+                VectorNode::Leaf(values) => return Some(values[i & (WIDTH - 1)].clone()),
+            }
+        }
+    }
+
+    /// Replaces element `i`, or appends if `i` is the length.
+    pub fn set(&self, i: usize, value: Value) -> Option<PVector> {
+        if i == self.len {
+            Some(self.push(value))
+        } else if i < self.len {
+            Some(PVector {
+                root: VectorNode::set(&self.root, self.shift, i, value),
+                ..self.clone()
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn push(&self, value: Value) -> PVector {
+        if self.len == WIDTH << self.shift {
+            let shift = self.shift + BITS;
+            PVector {
+                root: Rc::new(VectorNode::Branch(vec![
+                    self.root.clone(),
+                    VectorNode::path(self.shift, value),
+                ])),
+                len: self.len + 1,
+                shift,
+            }
+        } else {
+            PVector {
+                root: VectorNode::push(&self.root, self.shift, self.len, value),
+                len: self.len + 1,
+                shift: self.shift,
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn elements(&self) -> Vec<Value> {
+        let mut res = Vec::with_capacity(self.len);
+        self.root.collect(&mut res);
+        res
+    }
+}
+
+fn one_list(name: &str, args: &[Value]) -> Result<Vec<Value>, Exception> {
+    match args {
+        [list] => list_to_vec(list)
+            .ok_or_else(|| format!("Expected list argument to `{}`, got {:#?}", name, list).into()),
+        _ => Err(format!("Expected one argument to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+fn predicate(name: &str, args: &[Value], f: fn(&Value) -> bool) -> Result<Value, Exception> {
+    match args {
+        [v] => Ok(Value::Bool(f(v))),
+        _ => Err(format!("Expected one argument to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+/// Adds alternating keys and values to a map.
+fn pmap_from_pairs(name: &str, map: PMap, args: &[Value]) -> Result<PMap, Exception> {
+    if !args.len().is_multiple_of(2) {
+        return Err(format!(
+            "Expected keys and values in pairs to `{}`, got {:#?}",
+            name, args
+        )
+        .into());
+    }
+    args.chunks(2)
+        .try_fold(map, |map, kv| map.insert(kv[0].clone(), kv[1].clone()))
+}
+
+fn pset_from(set: PMap, elements: &[Value]) -> Result<PMap, Exception> {
+    elements
+        .iter()
+        .try_fold(set, |set, x| set.insert(x.clone(), Value::Nil))
+}
+
+fn pvector_from(v: PVector, elements: &[Value]) -> PVector {
+    elements.iter().fold(v, |v, x| v.push(x.clone()))
+}
+
+fn alist_to_pmap(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    one_list("alist->pmap", args)?
+        .into_iter()
+        .try_fold(PMap::default(), |map, pair| match pair {
+            Value::Pair(p) => {
+                let p = p.borrow();
+                map.insert(p.car.clone(), p.cdr.clone())
+            }
+            v => Err(format!("Expected a pair in `alist->pmap`, got {:#?}", v).into()),
+        })
+        .map(Value::PMap)
+}
+
+/// The elements of a persistent collection, in the order `seq` lists them: a map's entries are
+/// `(key . value)` pairs. Anything else gives `None`.
+pub fn elements(v: &Value) -> Option<Vec<Value>> {
+    match v {
+        Value::PMap(map) => Some(map.entries().into_iter().map(|(k, v)| cons(k, v)).collect()),
+        Value::PSet(set) => Some(set.entries().into_iter().map(|(k, _)| k).collect()),
+        Value::PVector(v) => Some(v.elements()),
+        _ => None,
+    }
+}
+
+/// Lists the elements of a persistent collection, or the `(key . value)` pairs of a map.
+fn seq(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [v] => elements(v).map(vec_to_list).ok_or_else(|| {
+            format!(
+                "Expected one persistent collection to `seq`, got {:#?}",
+                args
+            )
+            .into()
+        }),
+        _ => Err(format!(
+            "Expected one persistent collection to `seq`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(assoc coll key value ...)` sets keys of a map, or indices of a vector. The name is shared
+/// with the association list lookup, which hands persistent collections with keys and values
+/// over to this.
+pub fn assoc(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::PMap(map), rest @ ..] if !rest.is_empty() => {
+            pmap_from_pairs("assoc", map.clone(), rest).map(Value::PMap)
+        }
+        [Value::PVector(v), rest @ ..] if !rest.is_empty() && rest.len().is_multiple_of(2) => rest
+            .chunks(2)
+            .try_fold(v.clone(), |v, kv| {
+                let i = index_arg("assoc", &kv[0], v.len(), true)?;
+                Ok(v.set(i, kv[1].clone()).expect("The index was checked"))
+            })
+            .map(Value::PVector),
+        _ => Err(format!(
+            "Expected a pmap or pvector and keys and values to `assoc`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(dissoc coll key ...)` removes keys from a map or elements from a set.
+fn dissoc(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::PMap(map), keys @ ..] => keys
+            .iter()
+            .try_fold(map.clone(), |map, k| map.remove(k))
+            .map(Value::PMap),
+        [Value::PSet(set), keys @ ..] => keys
+            .iter()
+            .try_fold(set.clone(), |set, k| set.remove(k))
+            .map(Value::PSet),
+        _ => Err(format!(
+            "Expected a pmap or pset and keys to `dissoc`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(conj coll x ...)` adds elements to the end of a vector or to a set, or `(key . value)`
+/// pairs to a map.
+fn conj(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::PVector(v), xs @ ..] => Ok(Value::PVector(pvector_from(v.clone(), xs))),
+        [Value::PSet(set), xs @ ..] => pset_from(set.clone(), xs).map(Value::PSet),
+        [Value::PMap(map), xs @ ..] => xs
+            .iter()
+            .try_fold(map.clone(), |map, x| match x {
+                Value::Pair(p) => {
+                    let p = p.borrow();
+                    map.insert(p.car.clone(), p.cdr.clone())
+                }
+                v => Err(format!("Expected a pair to `conj` onto a pmap, got {:#?}", v).into()),
+            })
+            .map(Value::PMap),
+        _ => Err(format!(
+            "Expected a persistent collection and elements to `conj`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(get coll key [default])` looks up a key of a map, an index of a vector or an element of
+/// a set, returning `default` (or `#f`) if it is missing.
+fn get(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (coll, key, default) = match args {
+        [coll, key] => (coll, key, Value::Bool(false)),
+        [coll, key, default] => (coll, key, default.clone()),
+        _ => {
+            return Err(format!(
+                "Expected a collection, a key and an optional default to `get`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    let found = match coll {
+        Value::PMap(map) => map.get(key)?,
+        Value::PSet(set) => set.get(key)?.map(|_| key.clone()),
+        Value::PVector(v) => match key {
+            Value::Integer(n) if *n >= 0 => v.get(*n as usize),
+            _ => None,
+        },
+        v => return Err(format!("Expected a persistent collection to `get`, got {:#?}", v).into()),
+    };
+    Ok(found.unwrap_or(default))
+}
+
+fn contains(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::PMap(map), key] => Ok(Value::Bool(map.get(key)?.is_some())),
+        [Value::PSet(set), key] => Ok(Value::Bool(set.get(key)?.is_some())),
+        [Value::PVector(v), Value::Integer(n)] => {
+            Ok(Value::Bool(*n >= 0 && (*n as usize) < v.len()))
+        }
+        [Value::PVector(_), _] => Ok(Value::Bool(false)),
+        _ => Err(format!(
+            "Expected a collection and a key to `contains?`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// The number of elements in any collection: persistent ones, lists, vectors, strings and
+/// hash tables.
+fn count(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let n = match args {
+        [Value::PMap(map)] => map.len(),
+        [Value::PSet(set)] => set.len(),
+        [Value::PVector(v)] => v.len(),
+        [Value::Vector(v)] => v.borrow().len(),
+        [Value::String(s)] => s.chars().count(),
+        [Value::HashTable(t)] => t.borrow().len(),
+        [list] => list_to_vec(list)
+            .ok_or_else(|| format!("Expected a collection to `count`, got {:#?}", list))?
+            .len(),
+        _ => return Err(format!("Expected one argument to `count`, got {:#?}", args).into()),
+    };
+    Ok(Value::Integer(n as isize))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    const SQUARES: &str =
+        "(define m (fold-left (lambda (m i) (assoc m i (* i i))) (pmap) (iota 2000)))";
+    const NUMBERS: &str = "(define v (fold-left conj (pvector) (iota 2000)))";
+
+    #[test]
+    fn maps_with_many_keys() {
+        assert_eq!(
+            run(&format!(
+                "{} (list (count m) (get m 1999) (get m 2000) (get m 2000 'none))",
+                SQUARES
+            )),
+            Ok("(2000 3996001 #f none)".into())
+        );
+        assert_eq!(
+            run(&format!(
+                "{} (define fewer (fold-left dissoc m (iota 1990))) (list (count fewer) (count m) (sort (map car (seq fewer)) <))",
+                SQUARES
+            )),
+            Ok("(10 2000 (1990 1991 1992 1993 1994 1995 1996 1997 1998 1999))".into())
+        );
+    }
+
+    #[test]
+    fn vectors_with_many_elements() {
+        assert_eq!(
+            run(&format!(
+                "{} (list (count v) (get v 0) (get v 1057) (get v 1999) (get v 2000))",
+                NUMBERS
+            )),
+            Ok("(2000 0 1057 1999 #f)".into())
+        );
+        assert_eq!(
+            run(&format!(
+                "{} (define w (assoc v 1057 'x)) (list (get v 1057) (get w 1057))",
+                NUMBERS
+            )),
+            Ok("(1057 x)".into())
+        );
+    }
+
+    #[test]
+    fn sets() {
+        assert_eq!(
+            run("(define s (list->pset (iota 100))) (list (count s) (get s 50) (contains? s 100) (count (dissoc s 1 2 3)))"),
+            Ok("(100 50 #f 97)".into())
+        );
+    }
+
+    #[test]
+    fn contents_decide_equality() {
+        assert_eq!(
+            run("(list (equal? (pmap 'a 1 'b 2) (pmap 'b 2 'a 1)) (equal? (list->pset (list 1 2)) (pset 2 1)))"),
+            Ok("(#t #t)".into())
+        );
+        assert_eq!(
+            run("(list (pmap 'a 1) (pset 1) (conj (pmap) (cons 'k 'v)) (conj (pvector 1 2) 3))"),
+            Ok("({a 1} #{1} {k v} [1 2 3])".into())
+        );
+    }
+}
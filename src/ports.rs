@@ -0,0 +1,635 @@
+use crate::eval::{apply_procedure, Builtin, Value};
+use crate::exceptions::Exception;
+use crate::parser::parse_datum;
+use crate::tokenizer::tokenize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "current-output-port" => |args, _| match args {
+            [] => current_output_port(),
+            _ => Err(format!(
+                "Expected no arguments to `current-output-port`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "current-input-port" => |args, _| match args {
+            [] => current_input_port(),
+            _ => Err(format!(
+                "Expected no arguments to `current-input-port`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "open-input-string" => |args, _| match args {
+            [Value::String(s)] => Ok(input_port(Box::new(Cursor::new(s.clone().into_bytes())))),
+            _ => Err(format!(
+                "Expected one string to `open-input-string`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "open-output-string" => |args, _| match args {
+            [] => Ok(string_port()),
+            _ => Err(format!(
+                "Expected no arguments to `open-output-string`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "get-output-string" => |args, _| match args {
+            [port] => output_string(port),
+            _ => Err(format!("Expected one port to `get-output-string`, got {:#?}", args).into()),
+        },
+        "with-output-to-string" => |args, env| match args {
+            [thunk] => {
+                let port = string_port();
+                with_output_to(port.clone(), thunk, env)?;
+                output_string(&port)
+            }
+            _ => Err(format!(
+                "Expected one procedure to `with-output-to-string`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "call-with-output-string" => |args, env| match args {
+            [f] => {
+                let port = string_port();
+                apply_procedure(f, std::slice::from_ref(&port), env)?;
+                output_string(&port)
+            }
+            _ => Err(format!(
+                "Expected one procedure to `call-with-output-string`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "open-input-file" => |args, _| match args {
+            [Value::String(name)] => open_input_file(name),
+            _ => Err(format!(
+                "Expected one file name to `open-input-file`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "open-output-file" => |args, _| match args {
+            [Value::String(name), mode @ ..] => {
+                open_output_file(name, append("open-output-file", mode)?)
+            }
+            _ => Err(format!(
+                "Expected a file name and an optional 'append to `open-output-file`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "call-with-input-file" => |args, env| match args {
+            [Value::String(name), f] => {
+                let port = open_input_file(name)?;
+                let res = apply_procedure(f, std::slice::from_ref(&port), env);
+                close(&port)?;
+                res
+            }
+            _ => Err(format!(
+                "Expected a file name and a procedure to `call-with-input-file`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "call-with-output-file" => |args, env| {
+            match args {
+            [Value::String(name), f, mode @ ..] => {
+                let port = open_output_file(name, append("call-with-output-file", mode)?)?;
+                let res = apply_procedure(f, std::slice::from_ref(&port), env);
+                close(&port)?;
+                res
+            }
+            _ => Err(format!(
+                "Expected a file name, a procedure and an optional 'append to `call-with-output-file`, got {:#?}",
+                args
+            ).into()),
+        }
+        },
+        "with-output-to-file" => |args, env| {
+            match args {
+            [Value::String(name), thunk, mode @ ..] => {
+                let port = open_output_file(name, append("with-output-to-file", mode)?)?;
+                let res = with_output_to(port.clone(), thunk, env);
+                close(&port)?;
+                res
+            }
+            _ => Err(format!(
+                "Expected a file name, a procedure and an optional 'append to `with-output-to-file`, got {:#?}",
+                args
+            ).into()),
+        }
+        },
+        "close-port" | "close-input-port" | "close-output-port" => |args, _| match args {
+            [port] => {
+                close(port)?;
+                Ok(Value::Nil)
+            }
+            _ => Err(format!("Expected one port to `close-port`, got {:#?}", args).into()),
+        },
+        "read-char" => |args, _| {
+            read_from("read-char", args, |input| {
+                Ok(input.read_char()?.map_or(Value::Eof, Value::Char))
+            })
+        },
+        "peek-char" => |args, _| {
+            read_from("peek-char", args, |input| {
+                Ok(input.peek_char()?.map_or(Value::Eof, Value::Char))
+            })
+        },
+        "read" => |args, _| {
+            read_from("read", args, |input| match input.read_datum()? {
+                Some(text) => parse_datum(&mut tokenize(&text)?),
+                None => Ok(Value::Eof),
+            })
+        },
+        "read-line" => |args, _| read_from("read-line", args, Input::read_line),
+        "read-string" => |args, _| match args {
+            [Value::Integer(k), port @ ..] if *k >= 0 && port.len() <= 1 => {
+                with_input("read-string", port.first(), |input| {
+                    input.read_string(*k as usize)
+                })
+            }
+            _ => Err(format!(
+                "Expected a non-negative integer and an optional port to `read-string`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        // Strings and files can always be read without waiting. Interactive input may block,
+        // but there is no portable way to tell.
+        "char-ready?" => |args, _| read_from("char-ready?", args, |_| Ok(Value::Bool(true))),
+        "eof-object" => |args, _| match args {
+            [] => Ok(Value::Eof),
+            _ => Err(format!("Expected no arguments to `eof-object`, got {:#?}", args).into()),
+        },
+        "eof-object?" => |args, _| match args {
+            [v] => Ok(Value::Bool(matches!(v, Value::Eof))),
+            _ => Err(format!("Expected one argument to `eof-object?`, got {:#?}", args).into()),
+        },
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// Where the text written to an output port goes.
+pub enum Output {
+    Stream(Box<dyn Write>),
+    /// Text kept for `get-output-string`
+    String(String),
+}
+
+pub struct Input {
+    reader: Box<dyn BufRead>,
+    /// A char taken from the reader by `peek-char` but not read yet
+    peeked: Option<char>,
+}
+
+impl Input {
+    /// The next char, or `None` at the end of the input.
+    fn read_char(&mut self) -> Result<Option<char>, Exception> {
+        if let Some(c) = self.peeked.take() {
+            return Ok(Some(c));
+        }
+        let first = match self.reader.fill_buf().map_err(io_error)?.first() {
+            Some(&b) => b,
+            None => return Ok(None),
+        };
+        // The leading byte of a UTF-8 sequence says how many bytes follow it
+        let width = match first.leading_ones() {
+            0 => 1,
+            n @ 2..=4 => n as usize,
+            _ => return Err("Invalid UTF-8 in input!".into()),
+        };
+        let mut bytes = [0; 4];
+        self.reader
+            .read_exact(&mut bytes[..width])
+            .map_err(io_error)?;
+        match std::str::from_utf8(&bytes[..width]) {
+            Ok(s) => Ok(s.chars().next()),
+            Err(_) => Err("Invalid UTF-8 in input!".into()),
+        }
+    }
+
+    fn peek_char(&mut self) -> Result<Option<char>, Exception> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_char()?;
+        }
+        Ok(self.peeked)
+    }
+
+    /// The text of the next datum, read up to its last char so that whatever follows is left
+    /// for later reads, or `None` if only whitespace is left.
+    fn read_datum(&mut self) -> Result<Option<String>, Exception> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            let c = match self.peek_char()? {
+                Some(c) => c,
+                None if text.trim().is_empty() => return Ok(None),
+                None => return Err("Unexpected EOF while reading a datum!".into()),
+            };
+            self.read_char()?;
+            if c == ';' {
+                while self.peek_char()?.is_some_and(|c| c != '\n') {
+                    self.read_char()?;
+                }
+                continue;
+            }
+            text.push(c);
+            match c {
+                c if c.is_whitespace() => continue,
+                // A quote applies to the datum after it
+                '\'' => continue,
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                '"' | '|' => self.read_quoted(c, &mut text)?,
+                c => self.read_atom(c, &mut text, &mut depth)?,
+            }
+            if depth <= 0 {
+                return Ok(Some(text));
+            }
+        }
+    }
+
+    /// Reads the rest of a string literal or `|...|` symbol whose opening `quote` is already in
+    /// `text`, leaving escapes for the tokenizer.
+    fn read_quoted(&mut self, quote: char, text: &mut String) -> Result<(), Exception> {
+        loop {
+            match self.read_char()? {
+                Some(c) if c == quote => {
+                    text.push(c);
+                    return Ok(());
+                }
+                Some('\\') => {
+                    text.push('\\');
+                    if let Some(c) = self.read_char()? {
+                        text.push(c);
+                    }
+                }
+                Some(c) => text.push(c),
+                None if quote == '|' => return Err("Unterminated |symbol|!".into()),
+                None => return Err("Unterminated string literal!".into()),
+            }
+        }
+    }
+
+    /// Reads the rest of a number, identifier, char or other token starting with `first`, which
+    /// is already in `text`. `#(` and `#u8(` open a vector and so add to `depth`.
+    fn read_atom(
+        &mut self,
+        first: char,
+        text: &mut String,
+        depth: &mut i32,
+    ) -> Result<(), Exception> {
+        let mut atom = first.to_string();
+        while let Some(c) = self.peek_char()? {
+            if c == '(' && (atom == "#" || atom == "#u8") {
+                self.read_char()?;
+                text.push(c);
+                *depth += 1;
+                return Ok(());
+            }
+            // The char after `#\` is part of the literal, even if it is a delimiter
+            let delimiter = c.is_whitespace() || "()\"|".contains(c);
+            if delimiter && atom != "#\\" {
+                return Ok(());
+            }
+            self.read_char()?;
+            text.push(c);
+            atom.push(c);
+        }
+        Ok(())
+    }
+
+    /// The rest of the line without its line ending, or the eof object if nothing is left.
+    fn read_line(&mut self) -> Result<Value, Exception> {
+        let mut line = String::new();
+        loop {
+            match self.read_char()? {
+                None if line.is_empty() => return Ok(Value::Eof),
+                None | Some('\n') => break,
+                Some(c) => line.push(c),
+            }
+        }
+        if line.ends_with('\r') {
+            line.pop();
+        }
+        Ok(Value::String(line))
+    }
+
+    /// Up to `k` chars, or the eof object if there are none left to read.
+    fn read_string(&mut self, k: usize) -> Result<Value, Exception> {
+        let mut s = String::new();
+        for _ in 0..k {
+            match self.read_char()? {
+                Some(c) => s.push(c),
+                None if s.is_empty() => return Ok(Value::Eof),
+                None => break,
+            }
+        }
+        Ok(Value::String(s))
+    }
+}
+
+/// An input or output port. The contents are `None` once the port is closed.
+pub enum Port {
+    Input(Option<Input>),
+    Output(Option<Output>),
+}
+
+impl std::fmt::Debug for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Port::Input(_) => write!(f, "#<input-port>"),
+            Port::Output(_) => write!(f, "#<output-port>"),
+        }
+    }
+}
+
+thread_local! {
+    /// The ports used when no port is given, which the host sets up before evaluating
+    /// anything. `with-output-to-string` replaces the output port while it runs.
+    static CURRENT_OUTPUT_PORT: RefCell<Option<Value>> = const { RefCell::new(None) };
+    static CURRENT_INPUT_PORT: RefCell<Option<Value>> = const { RefCell::new(None) };
+}
+
+/// Sends everything written to the current output port to `writer`.
+pub fn set_current_output_port(writer: Box<dyn Write>) {
+    let port = Value::Port(Rc::new(RefCell::new(Port::Output(Some(Output::Stream(
+        writer,
+    ))))));
+    CURRENT_OUTPUT_PORT.with(|p| *p.borrow_mut() = Some(port));
+}
+
+/// Makes the current input port read from `reader`.
+pub fn set_current_input_port(reader: Box<dyn BufRead>) {
+    let port = input_port(reader);
+    CURRENT_INPUT_PORT.with(|p| *p.borrow_mut() = Some(port));
+}
+
+/// The standard input, read through the buffer it shares with the rest of the program instead
+/// of one of its own, so that reading from a port never takes input the REPL reads later.
+#[derive(Default)]
+pub struct SharedStdin {
+    /// A copy of what stdin has buffered, up to the end of the line, which stays in stdin's
+    /// buffer until it is consumed
+    buf: Vec<u8>,
+}
+
+impl Read for SharedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.buf.clear();
+        std::io::stdin().lock().read(buf)
+    }
+}
+
+impl BufRead for SharedStdin {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        let mut stdin = std::io::stdin().lock();
+        let buffered = stdin.fill_buf()?;
+        let line = buffered
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(buffered.len(), |i| i + 1);
+        self.buf = buffered[..line].to_vec();
+        Ok(&self.buf)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf.clear();
+        std::io::stdin().lock().consume(amt);
+    }
+}
+
+fn current_output_port() -> Result<Value, Exception> {
+    CURRENT_OUTPUT_PORT
+        .with(|p| p.borrow().clone())
+        .ok_or_else(|| "There is no current output port!".into())
+}
+
+fn current_input_port() -> Result<Value, Exception> {
+    CURRENT_INPUT_PORT
+        .with(|p| p.borrow().clone())
+        .ok_or_else(|| "There is no current input port!".into())
+}
+
+fn input_port(reader: Box<dyn BufRead>) -> Value {
+    Value::Port(Rc::new(RefCell::new(Port::Input(Some(Input {
+        reader,
+        peeked: None,
+    })))))
+}
+
+fn string_port() -> Value {
+    Value::Port(Rc::new(RefCell::new(Port::Output(Some(Output::String(
+        String::new(),
+    ))))))
+}
+
+fn output_string(port: &Value) -> Result<Value, Exception> {
+    if let Value::Port(p) = port {
+        if let Port::Output(Some(Output::String(s))) = &*p.borrow() {
+            return Ok(Value::String(s.clone()));
+        }
+    }
+    Err(format!("Expected a string output port, got {:#?}", port).into())
+}
+
+fn io_error(e: std::io::Error) -> Exception {
+    format!("I/O error: {}", e).into()
+}
+
+/// Calls `thunk` with `port` as the current output port, putting the old one back after.
+fn with_output_to(
+    port: Value,
+    thunk: &Value,
+    env: &mut HashMap<String, Value>,
+) -> Result<Value, Exception> {
+    let old = CURRENT_OUTPUT_PORT.with(|p| p.replace(Some(port)));
+    let res = apply_procedure(thunk, &[], env);
+    CURRENT_OUTPUT_PORT.with(|p| *p.borrow_mut() = old);
+    res
+}
+
+/// Writes `s` to `port`, or to the current output port if none is given.
+pub fn write_str(port: Option<&Value>, s: &str) -> Result<(), Exception> {
+    let port = match port {
+        Some(port) => port.clone(),
+        None => current_output_port()?,
+    };
+    match &port {
+        Value::Port(p) => match &mut *p.borrow_mut() {
+            Port::Output(Some(Output::Stream(w))) => w
+                .write_all(s.as_bytes())
+                .and_then(|_| w.flush())
+                .map_err(io_error),
+            Port::Output(Some(Output::String(buf))) => {
+                buf.push_str(s);
+                Ok(())
+            }
+            Port::Output(None) => Err("Can't write to a closed port!".into()),
+            Port::Input(_) => Err("Can't write to an input port!".into()),
+        },
+        v => Err(format!("Expected an output port, got {:#?}", v).into()),
+    }
+}
+
+/// Reads from the port given to `name`, or the current input port.
+fn read_from(
+    name: &str,
+    args: &[Value],
+    f: fn(&mut Input) -> Result<Value, Exception>,
+) -> Result<Value, Exception> {
+    match args {
+        [] | [_] => with_input(name, args.first(), f),
+        _ => Err(format!("Expected an optional port to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+fn with_input<F>(name: &str, port: Option<&Value>, f: F) -> Result<Value, Exception>
+where
+    F: FnOnce(&mut Input) -> Result<Value, Exception>,
+{
+    let port = match port {
+        Some(port) => port.clone(),
+        None => current_input_port()?,
+    };
+    match &port {
+        Value::Port(p) => match &mut *p.borrow_mut() {
+            Port::Input(Some(input)) => f(input),
+            Port::Input(None) => Err(format!("Can't `{}` from a closed port!", name).into()),
+            Port::Output(_) => Err(format!("Can't `{}` from an output port!", name).into()),
+        },
+        v => Err(format!("Expected an input port to `{}`, got {:#?}", name, v).into()),
+    }
+}
+
+fn open_input_file(name: &str) -> Result<Value, Exception> {
+    let file = File::open(name).map_err(|e| format!("Couldn't open {}: {}", name, e))?;
+    Ok(input_port(Box::new(BufReader::new(file))))
+}
+
+fn open_output_file(name: &str, append: bool) -> Result<Value, Exception> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(name)
+        .map_err(|e| format!("Couldn't open {}: {}", name, e))?;
+    Ok(Value::Port(Rc::new(RefCell::new(Port::Output(Some(
+        Output::Stream(Box::new(file)),
+    ))))))
+}
+
+/// Whether the optional mode given to `name` asks for output to be added to the end of the
+/// file instead of replacing it.
+fn append(name: &str, mode: &[Value]) -> Result<bool, Exception> {
+    match mode {
+        [] => Ok(false),
+        [Value::Symbol(s)] if s == "append" => Ok(true),
+        _ => Err(format!(
+            "Expected 'append as the mode to `{}`, got {:#?}",
+            name, mode
+        )
+        .into()),
+    }
+}
+
+/// Closes `port`, flushing any output. Closing a port twice does nothing.
+fn close(port: &Value) -> Result<(), Exception> {
+    match port {
+        Value::Port(p) => {
+            match &mut *p.borrow_mut() {
+                Port::Input(input) => *input = None,
+                Port::Output(output) => {
+                    if let Some(Output::Stream(w)) = output {
+                        w.flush().map_err(io_error)?;
+                    }
+                    *output = None;
+                }
+            }
+            Ok(())
+        }
+        v => Err(format!("Expected a port to close, got {:#?}", v).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn string_input_ports() {
+        assert_eq!(
+            run("(define q (open-input-string \"hi\")) (list (read-char q) (peek-char q) (read-char q) (read-char q))"),
+            Ok("(#\\h #\\i #\\i #<eof>)".into())
+        );
+        assert_eq!(
+            run("(list (read-line (open-input-string \"one\\ntwo\")) (read-string 3 (open-input-string \"hello\")) (read-line (open-input-string \"\")))"),
+            Ok("(\"one\" \"hel\" #<eof>)".into())
+        );
+        assert_eq!(
+            run("(define p (open-input-string \"abc\")) (close-port p) (read-char p)"),
+            Err("Can't `read-char` from a closed port!".into())
+        );
+    }
+
+    #[test]
+    fn string_output_ports() {
+        assert_eq!(
+            run("(define o (open-output-string)) (write \"a\" o) (display 1 o) (get-output-string o)"),
+            Ok(r#""\"a\"1""#.into())
+        );
+        assert_eq!(
+            run("(list (with-output-to-string (lambda () (write \"hi\"))) (call-with-output-string (lambda (port) (display 42 port))))"),
+            Ok(r#"("\"hi\"" "42")"#.into())
+        );
+        assert_eq!(
+            run("(list (port? (open-output-string)) (input-port? (open-input-string \"\")) (output-port? (open-input-string \"\")))"),
+            Ok("(#t #t #f)".into())
+        );
+    }
+
+    #[test]
+    fn file_ports() {
+        let path = std::env::temp_dir().join("file_ports.txt");
+        let path = format!("{:?}", path.display().to_string());
+        let source = format!(
+            "(call-with-output-file {path} (lambda (port) (write-string \"one\n\" port)))
+            (with-output-to-file {path} (lambda () (display \"two\")) 'append)
+            (call-with-input-file {path} (lambda (port) (list (read-line port) (read-line port) (read-line port))))"
+        );
+        assert_eq!(run(&source), Ok("(\"one\" \"two\" #<eof>)".into()));
+        let missing = std::env::temp_dir().join("file_ports_missing.txt");
+        assert!(run(&format!(
+            "(open-input-file {:?})",
+            missing.display().to_string()
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn read_gives_data() {
+        assert_eq!(
+            run("(read (open-input-string \"(a \\\"b\\\" 3) rest\"))"),
+            Ok("(a \"b\" 3)".into())
+        );
+        assert_eq!(
+            run("(define p (open-input-string \"#(1 x) |a b| #\\\\a 'q rest\")) (list (read p) (read p) (read p) (read p) (read-char p) (read-line p))"),
+            Ok("(#(1 x) |a b| #\\a (quote q) #\\space \"rest\")".into())
+        );
+        assert_eq!(run("(read (open-input-string \"\"))"), Ok("#<eof>".into()));
+        assert!(run("(read (open-input-string \"(1 . \"))").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::environments::{eval_top_level, with_top_level};
+use crate::eval::Value;
+use crate::exceptions::Exception;
+use crate::parser::parse_all;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The source of the standard prelude, built into the binary.
+const PRELUDE: &str = include_str!("prelude.scm");
+
+/// A new environment holding the prelude's definitions, which are evaluated at a top level of
+/// their own so that they can't see the program's.
+pub fn environment() -> Result<HashMap<String, Value>, Exception> {
+    let top = Rc::new(RefCell::new(HashMap::new()));
+    with_top_level(top.clone(), || {
+        for expr in parse_all(PRELUDE)? {
+            eval_top_level(&expr)?;
+        }
+        Ok::<(), Exception>(())
+    })?;
+    Ok(top.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::environment;
+    use crate::eval::run;
+
+    #[test]
+    fn prelude_procedures() {
+        assert_eq!(
+            run("(list (zero? 0) (positive? -1) (negative? -1) (abs -3) (square 7))"),
+            Ok("(#t #f #t 3 49)".into())
+        );
+        assert_eq!(
+            run("(list (cadr '(1 2 3)) (caddr '(1 2 3)) (cdar '((1 . 2))))"),
+            Ok("(2 3 2)".into())
+        );
+        assert_eq!(
+            run("(list ((compose square abs) -3) (identity 'x) (append-map (lambda (x) (list x x)) '(1 2)))"),
+            Ok("(9 x (1 1 2 2))".into())
+        );
+    }
+
+    #[test]
+    fn redefinitions_leave_the_prelude_alone() {
+        assert_eq!(
+            run("(define cddr (lambda (p) 'mine)) (caddr '(1 2 3))"),
+            Ok("3".into())
+        );
+        assert!(environment().is_ok_and(|env| env.contains_key("list-copy")));
+    }
+}
@@ -0,0 +1,50 @@
+; The standard prelude: procedures that are easy to write in the language itself. It is
+; evaluated into the initial environment unless the interpreter is started with --no-prelude.
+
+; Predicates
+(define zero?
+  (lambda (n)
+    (= n 0)))
+
+(define positive?
+  (lambda (n)
+    (< 0 n)))
+
+(define negative?
+  (lambda (n)
+    (< n 0)))
+
+; Arithmetic
+(define abs
+  (lambda (n)
+    (if (< n 0) (- n) n)))
+
+(define square
+  (lambda (n)
+    (* n n)))
+
+; Pairs and lists
+(define caar (lambda (p) (car (car p))))
+(define cadr (lambda (p) (car (cdr p))))
+(define cdar (lambda (p) (cdr (car p))))
+(define cddr (lambda (p) (cdr (cdr p))))
+(define caddr (lambda (p) (car (cddr p))))
+(define cdddr (lambda (p) (cdr (cddr p))))
+(define cadddr (lambda (p) (car (cdddr p))))
+
+(define list-copy
+  (lambda (l)
+    (fold-right cons '() l)))
+
+(define append-map
+  (lambda (f l)
+    (apply append (map f l))))
+
+; Procedures
+(define identity
+  (lambda (x)
+    x))
+
+(define compose
+  (lambda (f g)
+    (lambda (x) (f (g x)))))
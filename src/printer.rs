@@ -0,0 +1,400 @@
+use crate::chars;
+use crate::eval::{Builtin, Value};
+use crate::exceptions::Exception;
+use crate::numeric;
+use crate::ports::{self, Port};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "display" => |args, _| print_one("display", args, Style::Display, Labels::Cycles),
+        "write" => |args, _| print_one("write", args, Style::Write, Labels::Cycles),
+        "write-shared" => |args, _| print_one("write-shared", args, Style::Write, Labels::Shared),
+        "write-simple" => |args, _| print_one("write-simple", args, Style::Write, Labels::None),
+        "write-string" => |args, _| match args {
+            [Value::String(s), port @ ..] if port.len() <= 1 => output(port.first(), s),
+            _ => Err(format!(
+                "Expected a string and an optional port to `write-string`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "write-char" => |args, _| match args {
+            [Value::Char(c), port @ ..] if port.len() <= 1 => output(port.first(), &c.to_string()),
+            _ => Err(format!(
+                "Expected a char and an optional port to `write-char`, got {:#?}",
+                args
+            )
+            .into()),
+        },
+        "newline" => |args, _| match args {
+            [] | [_] => output(args.first(), "\n"),
+            _ => Err(format!("Expected an optional port to `newline`, got {:#?}", args).into()),
+        },
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// Writes `s` to `port`, or the current output port.
+fn output(port: Option<&Value>, s: &str) -> Result<Value, Exception> {
+    ports::write_str(port, s)?;
+    Ok(Value::Nil)
+}
+
+fn print_one(name: &str, args: &[Value], style: Style, labels: Labels) -> Result<Value, Exception> {
+    match args {
+        [v, port @ ..] if port.len() <= 1 => output(port.first(), &print(v, style, labels)),
+        _ => Err(format!(
+            "Expected a value and an optional port to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// Whether strings, chars and symbols are printed as their text (`display`) or as they would
+/// be written in source code (`write`).
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    Display,
+    Write,
+}
+
+/// Which objects get datum labels like `#0=(a . #0#)`: none, only those that are part of a
+/// cycle (so that printing always ends), or every object that appears more than once.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Labels {
+    None,
+    Cycles,
+    Shared,
+}
+
+pub fn print(v: &Value, style: Style, labels: Labels) -> String {
+    let mut printer = Printer {
+        style,
+        labelled: HashSet::new(),
+        assigned: HashMap::new(),
+        out: String::new(),
+    };
+    if labels != Labels::None {
+        let mut scan = Scan {
+            shared: labels == Labels::Shared,
+            visited: HashSet::new(),
+            in_progress: HashSet::new(),
+            labelled: HashSet::new(),
+        };
+        scan.scan(v);
+        printer.labelled = scan.labelled;
+    }
+    printer.print(v);
+    printer.out
+}
+
+/// The address of an object that can be part of a cycle, which identifies it.
+fn identity(v: &Value) -> Option<usize> {
+    match v {
+        Value::Pair(p) => Some(Rc::as_ptr(p) as usize),
+        Value::Vector(v) => Some(Rc::as_ptr(v) as usize),
+        Value::Record(r) => Some(Rc::as_ptr(r) as usize),
+        _ => None,
+    }
+}
+
+/// The values printed inside `v`, other than those in the cdr of a pair.
+fn children(v: &Value) -> Vec<Value> {
+    match v {
+        Value::Pair(p) => vec![p.borrow().car.clone()],
+        Value::Vector(v) => v.borrow().clone(),
+        Value::Record(r) => r.fields.borrow().clone(),
+        Value::PMap(map) => map
+            .entries()
+            .into_iter()
+            .flat_map(|(k, v)| vec![k, v])
+            .collect(),
+        Value::PSet(set) => set.entries().into_iter().map(|(k, _)| k).collect(),
+        Value::PVector(v) => v.elements(),
+        _ => Vec::new(),
+    }
+}
+
+/// Finds the objects that need labels before printing starts.
+struct Scan {
+    shared: bool,
+    visited: HashSet<usize>,
+    in_progress: HashSet<usize>,
+    labelled: HashSet<usize>,
+}
+
+impl Scan {
+    fn scan(&mut self, v: &Value) {
+        // The cdrs of a list are followed in a loop rather than recursively, so long lists
+        // don't overflow the stack. The pairs stay in progress until the whole list is done.
+        let mut entered = Vec::new();
+        let mut current = v.clone();
+        loop {
+            if let Some(id) = identity(&current) {
+                if self.visited.contains(&id) {
+                    if self.shared || self.in_progress.contains(&id) {
+                        self.labelled.insert(id);
+                    }
+                    break;
+                }
+                self.visited.insert(id);
+                self.in_progress.insert(id);
+                entered.push(id);
+            }
+            for child in children(&current) {
+                self.scan(&child);
+            }
+            current = match &current {
+                Value::Pair(p) => p.borrow().cdr.clone(),
+                _ => break,
+            };
+        }
+        for id in entered {
+            self.in_progress.remove(&id);
+        }
+    }
+}
+
+struct Printer {
+    style: Style,
+    labelled: HashSet<usize>,
+    /// The numbers of the labels printed so far
+    assigned: HashMap<usize, usize>,
+    out: String,
+}
+
+impl Printer {
+    /// Prints the label of `v` if it needs one. Returns false if it has already been printed,
+    /// so that only a reference to the label is needed.
+    fn label(&mut self, v: &Value) -> bool {
+        let id = match identity(v) {
+            Some(id) if self.labelled.contains(&id) => id,
+            _ => return true,
+        };
+        match self.assigned.get(&id) {
+            Some(n) => {
+                self.out.push_str(&format!("#{}#", n));
+                false
+            }
+            None => {
+                let n = self.assigned.len();
+                self.assigned.insert(id, n);
+                self.out.push_str(&format!("#{}=", n));
+                true
+            }
+        }
+    }
+
+    fn sequence(&mut self, open: &str, values: &[Value], close: &str) {
+        self.out.push_str(open);
+        for (i, x) in values.iter().enumerate() {
+            if i > 0 {
+                self.out.push(' ');
+            }
+            self.print(x);
+        }
+        self.out.push_str(close);
+    }
+
+    fn print(&mut self, v: &Value) {
+        if !self.label(v) {
+            return;
+        }
+        match v {
+            Value::Number(n) => self.out.push_str(&numeric::format_float(*n)),
+            Value::Integer(n) => self.out.push_str(&n.to_string()),
+            Value::Complex(re, im) => self.out.push_str(&numeric::format_complex(*re, *im)),
+            Value::Bool(true) => self.out.push_str("#t"),
+            Value::Bool(false) => self.out.push_str("#f"),
+            Value::Char(c) => match self.style {
+                Style::Display => self.out.push(*c),
+                Style::Write => self.out.push_str(&format!("#\\{}", chars::char_name(*c))),
+            },
+            Value::String(s) => match self.style {
+                Style::Display => self.out.push_str(s),
+                Style::Write => self.out.push_str(&write_string(s)),
+            },
+            Value::Symbol(s) => match self.style {
+                Style::Display => self.out.push_str(s),
+                Style::Write => self.out.push_str(&write_symbol(s)),
+            },
+            Value::Nil => self.out.push_str("()"),
+            Value::Pair(p) => {
+                self.out.push('(');
+                self.print(&p.borrow().car.clone());
+                let mut rest = p.borrow().cdr.clone();
+                loop {
+                    rest = match &rest {
+                        Value::Nil => break,
+                        Value::Pair(p) if !self.labelled.contains(&(Rc::as_ptr(p) as usize)) => {
+                            self.out.push(' ');
+                            self.print(&p.borrow().car.clone());
+                            p.borrow().cdr.clone()
+                        }
+                        v => {
+                            self.out.push_str(" . ");
+                            self.print(v);
+                            break;
+                        }
+                    };
+                }
+                self.out.push(')');
+            }
+            Value::Vector(v) => {
+                let elements = v.borrow().clone();
+                self.sequence("#(", &elements, ")");
+            }
+            Value::Bytevector(b) => {
+                let bytes = b.borrow().iter().map(|b| b.to_string()).collect::<Vec<_>>();
+                self.out.push_str(&format!("#u8({})", bytes.join(" ")));
+            }
+            Value::PMap(map) => {
+                self.out.push('{');
+                for (i, (k, v)) in map.entries().iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.print(k);
+                    self.out.push(' ');
+                    self.print(v);
+                }
+                self.out.push('}');
+            }
+            Value::PSet(set) => {
+                let elements = set
+                    .entries()
+                    .into_iter()
+                    .map(|(k, _)| k)
+                    .collect::<Vec<_>>();
+                self.sequence("#{", &elements, "}");
+            }
+            Value::PVector(v) => self.sequence("[", &v.elements(), "]"),
+            Value::HashTable(t) => self
+                .out
+                .push_str(&format!("#<hash-table {}>", t.borrow().len())),
+            Value::Record(r) => {
+                self.out
+                    .push_str(&format!("#<{}", r.record_type.short_name()));
+                let fields = r.fields.borrow().clone();
+                for (field, v) in r.record_type.fields.iter().zip(fields.iter()) {
+                    self.out.push_str(&format!(" {}: ", field));
+                    self.print(v);
+                }
+                self.out.push('>');
+            }
+            Value::Function(_, _) => self.out.push_str("#<procedure>"),
+            Value::Builtin(name) => self.out.push_str(&format!("#<builtin {}>", name)),
+            Value::RecordProcedure(p) => self.out.push_str(&format!("#<procedure {}>", p.name)),
+            Value::Port(p) => match *p.borrow() {
+                Port::Input(_) => self.out.push_str("#<input-port>"),
+                Port::Output(_) => self.out.push_str("#<output-port>"),
+            },
+            Value::Eof => self.out.push_str("#<eof>"),
+            Value::Environment(_) => self.out.push_str("#<environment>"),
+            Value::Error(e) => {
+                self.out.push_str("#<error ");
+                self.out.push_str(&write_string(&e.message));
+                for v in &e.irritants {
+                    self.out.push(' ');
+                    self.print(v);
+                }
+                self.out.push('>');
+            }
+        }
+    }
+}
+
+/// A string literal that reads back as `s`.
+fn write_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            '\u{7}' => res.push_str("\\a"),
+            '\u{8}' => res.push_str("\\b"),
+            c if c.is_control() => res.push_str(&format!("\\x{:x};", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Symbols that wouldn't read back as themselves are written between bars.
+fn write_symbol(s: &str) -> String {
+    let plain = !s.is_empty()
+        && !s.starts_with('#')
+        && !s
+            .chars()
+            .any(|c| c.is_whitespace() || "()\"';|".contains(c))
+        && numeric::parse_number(s, 10).is_none();
+    if plain {
+        s.to_string()
+    } else {
+        format!("|{}|", s.replace('\\', "\\\\").replace('|', "\\|"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    /// What `print` writes, as a string literal.
+    fn printed(definitions: &str, print: &str) -> Result<String, String> {
+        run(&format!(
+            "{} (with-output-to-string (lambda () {}))",
+            definitions, print
+        ))
+    }
+
+    #[test]
+    fn display_and_write() {
+        assert_eq!(
+            printed("", "(display (list \"a\" #\\b 'c '(1 . 2)))"),
+            Ok(r##""(a b c (1 . 2))""##.into())
+        );
+        assert_eq!(
+            printed("", "(write (list \"a\\n\" #\\b #\\space '|a b| '|| 'abc))"),
+            Ok(r##""(\"a\\n\" #\\b #\\space |a b| || abc)""##.into())
+        );
+    }
+
+    #[test]
+    fn cycles_are_labelled() {
+        let cyclic = "(define x (list 1 2)) (set-cdr! (cdr x) x)";
+        assert_eq!(
+            printed(cyclic, "(write x)"),
+            Ok(r##""#0=(1 2 . #0#)""##.into())
+        );
+        let cyclic = "(define v (vector 1 2)) (vector-set! v 1 v)";
+        assert_eq!(
+            printed(cyclic, "(display v)"),
+            Ok(r##""#0=#(1 #0#)""##.into())
+        );
+    }
+
+    #[test]
+    fn only_write_shared_labels_shared_structure() {
+        let shared = "(define y (list 1 2))";
+        assert_eq!(
+            printed(shared, "(write-shared (list y y))"),
+            Ok(r##""(#0=(1 2) #0#)""##.into())
+        );
+        assert_eq!(
+            printed(shared, "(write (list y y))"),
+            Ok(r##""((1 2) (1 2))""##.into())
+        );
+        assert_eq!(
+            printed(shared, "(write-simple (list y y))"),
+            Ok(r##""((1 2) (1 2))""##.into())
+        );
+    }
+}
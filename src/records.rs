@@ -0,0 +1,248 @@
+use crate::eval::{list_to_vec, Value};
+use crate::exceptions::Exception;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A type made by `define-record-type`. Every definition makes a distinct type, even if an
+/// earlier one had the same name.
+#[derive(Debug)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl RecordType {
+    /// The type's name without the conventional angle brackets, so `<point>` is `point`.
+    pub fn short_name(&self) -> &str {
+        self.name.trim_start_matches('<').trim_end_matches('>')
+    }
+}
+
+#[derive(Debug)]
+pub struct Record {
+    pub record_type: Rc<RecordType>,
+    pub fields: RefCell<Vec<Value>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum RecordOp {
+    /// Takes the values of the listed fields, leaving the rest unset
+    Constructor(Vec<usize>),
+    Predicate,
+    Accessor(usize),
+    Modifier(usize),
+}
+
+/// One of the procedures generated by `define-record-type`.
+#[derive(Debug)]
+pub struct RecordProcedure {
+    pub name: String,
+    pub record_type: Rc<RecordType>,
+    pub op: RecordOp,
+}
+
+/// A parsed `(define-record-type <name> (constructor field ...) predicate (field accessor
+/// [modifier]) ...)` form.
+#[derive(Clone, Debug)]
+pub struct RecordSpec {
+    name: String,
+    constructor: Option<(String, Vec<String>)>,
+    predicate: String,
+    fields: Vec<(String, String, Option<String>)>,
+}
+
+fn identifier(what: &str, v: &Value) -> Result<String, Exception> {
+    match v {
+        Value::Symbol(s) => Ok(s.clone()),
+        v => Err(format!(
+            "Expected an identifier for the {} in define-record-type, got {}",
+            what, v
+        )
+        .into()),
+    }
+}
+
+impl RecordSpec {
+    /// Reads the parts of a `define-record-type` form following the keyword, as data.
+    pub fn new(parts: &[Value]) -> Result<RecordSpec, Exception> {
+        let (name, constructor, predicate, fields) = match parts {
+            [name, constructor, predicate, fields @ ..] => (name, constructor, predicate, fields),
+            _ => {
+                return Err(
+                    "Expected a name, a constructor and a predicate in define-record-type!".into(),
+                )
+            }
+        };
+        let fields = fields
+            .iter()
+            .map(|field| match list_to_vec(field).as_deref() {
+                Some([field, accessor]) => Ok((
+                    identifier("field name", field)?,
+                    identifier("accessor", accessor)?,
+                    None,
+                )),
+                Some([field, accessor, modifier]) => Ok((
+                    identifier("field name", field)?,
+                    identifier("accessor", accessor)?,
+                    Some(identifier("modifier", modifier)?),
+                )),
+                _ => Err(format!(
+                    "Expected (field accessor [modifier]) in define-record-type, got {}",
+                    field
+                )
+                .into()),
+            })
+            .collect::<Result<Vec<_>, Exception>>()?;
+        let constructor = match constructor {
+            Value::Bool(false) => None,
+            // A bare name takes every field, in order
+            Value::Symbol(s) => Some((s.clone(), fields.iter().map(|f| f.0.clone()).collect())),
+            v => match list_to_vec(v).as_deref() {
+                Some([name, args @ ..]) => Some((
+                    identifier("constructor", name)?,
+                    args.iter()
+                        .map(|arg| identifier("constructor argument", arg))
+                        .collect::<Result<Vec<String>, Exception>>()?,
+                )),
+                _ => {
+                    return Err(format!(
+                        "Expected (constructor field ...) in define-record-type, got {}",
+                        v
+                    )
+                    .into())
+                }
+            },
+        };
+        Ok(RecordSpec {
+            name: identifier("type name", name)?,
+            constructor,
+            predicate: identifier("predicate", predicate)?,
+            fields,
+        })
+    }
+}
+
+/// Makes a new record type and binds its procedures in `env`.
+pub fn define_record_type(
+    spec: &RecordSpec,
+    env: &mut HashMap<String, Value>,
+) -> Result<(), Exception> {
+    let record_type = Rc::new(RecordType {
+        name: spec.name.clone(),
+        fields: spec.fields.iter().map(|f| f.0.clone()).collect(),
+    });
+    let index = |field: &str| {
+        record_type
+            .fields
+            .iter()
+            .position(|f| f == field)
+            .ok_or_else(|| format!("{} is not a field of {}!", field, record_type.name).into())
+    };
+    let mut procedures = Vec::new();
+    if let Some((name, args)) = &spec.constructor {
+        let indices = args
+            .iter()
+            .map(|arg| index(arg))
+            .collect::<Result<Vec<usize>, Exception>>()?;
+        procedures.push((name.clone(), RecordOp::Constructor(indices)));
+    }
+    procedures.push((spec.predicate.clone(), RecordOp::Predicate));
+    for (i, (_, accessor, modifier)) in spec.fields.iter().enumerate() {
+        procedures.push((accessor.clone(), RecordOp::Accessor(i)));
+        if let Some(modifier) = modifier {
+            procedures.push((modifier.clone(), RecordOp::Modifier(i)));
+        }
+    }
+    for (name, op) in procedures {
+        let procedure = RecordProcedure {
+            name: name.clone(),
+            record_type: record_type.clone(),
+            op,
+        };
+        env.insert(name, Value::RecordProcedure(Rc::new(procedure)));
+    }
+    Ok(())
+}
+
+/// The record `v`, if it is one of type `record_type`.
+fn record<'a>(procedure: &RecordProcedure, v: &'a Value) -> Result<&'a Rc<Record>, Exception> {
+    match v {
+        Value::Record(r) if Rc::ptr_eq(&r.record_type, &procedure.record_type) => Ok(r),
+        v => Err(format!(
+            "Expected a {} record to `{}`, got {:#?}",
+            procedure.record_type.name, procedure.name, v
+        )
+        .into()),
+    }
+}
+
+pub fn apply(procedure: &RecordProcedure, args: &[Value]) -> Result<Value, Exception> {
+    match (&procedure.op, args) {
+        (RecordOp::Constructor(indices), args) if args.len() == indices.len() => {
+            let mut fields = vec![Value::Bool(false); procedure.record_type.fields.len()];
+            for (&i, arg) in indices.iter().zip(args) {
+                fields[i] = arg.clone();
+            }
+            Ok(Value::Record(Rc::new(Record {
+                record_type: procedure.record_type.clone(),
+                fields: RefCell::new(fields),
+            })))
+        }
+        (RecordOp::Predicate, [v]) => Ok(Value::Bool(matches!(
+            v,
+            Value::Record(r) if Rc::ptr_eq(&r.record_type, &procedure.record_type)
+        ))),
+        (RecordOp::Accessor(i), [r]) => Ok(record(procedure, r)?.fields.borrow()[*i].clone()),
+        (RecordOp::Modifier(i), [r, v]) => {
+            record(procedure, r)?.fields.borrow_mut()[*i] = v.clone();
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Wrong number of arguments to `{}`, got {:#?}",
+            procedure.name, args
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    const POINT: &str =
+        "(define-record-type <point> (make-point x y) point? (x point-x set-point-x!) (y point-y))";
+
+    #[test]
+    fn constructors_accessors_and_modifiers() {
+        assert_eq!(
+            run(&format!("{} (make-point 1 2)", POINT)),
+            Ok("#<point x: 1 y: 2>".into())
+        );
+        assert_eq!(
+            run(&format!(
+                "{} (define p (make-point 1 2)) (set-point-x! p 10) (list (point-x p) (point-y p) (point? p) (point? 5) (type-of p))",
+                POINT
+            )),
+            Ok("(10 2 #t #f point)".into())
+        );
+        assert_eq!(
+            run("(define-record-type <cell> (make-cell) cell? (v cell-v)) (cell-v (make-cell))"),
+            Ok("#f".into())
+        );
+        assert!(run(&format!("{} (point-x 5)", POINT)).is_err());
+        assert!(run(&format!("{} (make-point 1)", POINT)).is_err());
+    }
+
+    #[test]
+    fn every_definition_makes_a_new_type() {
+        assert_eq!(
+            run(&format!(
+                "{} (define p (make-point 1 2)) (define-record-type <point> (make-point x) point? (x point-x)) (point? p)",
+                POINT
+            )),
+            Ok("#f".into())
+        );
+        assert!(run("(define-record-type <point> (make-point x y) point? (x point-x))").is_err());
+    }
+}
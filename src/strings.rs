@@ -0,0 +1,455 @@
+use crate::eval::{
+    apply_procedure, index_arg, list_to_vec, range_args, vec_to_list, Builtin, Value,
+};
+use crate::exceptions::Exception;
+use std::collections::HashMap;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "string->list" => string_to_list,
+        "list->string" => list_to_string,
+        "string" => string,
+        "make-string" => make_string,
+        "string-length" => string_length,
+        "string-ref" => string_ref,
+        "substring" => substring,
+        "string-copy" => string_copy,
+        "string-append" => string_append,
+        "string=?" => |args, _| compare("string=?", args, false, |a, b| a == b),
+        "string<?" => |args, _| compare("string<?", args, false, |a, b| a < b),
+        "string>?" => |args, _| compare("string>?", args, false, |a, b| a > b),
+        "string<=?" => |args, _| compare("string<=?", args, false, |a, b| a <= b),
+        "string>=?" => |args, _| compare("string>=?", args, false, |a, b| a >= b),
+        "string-ci=?" => |args, _| compare("string-ci=?", args, true, |a, b| a == b),
+        "string-ci<?" => |args, _| compare("string-ci<?", args, true, |a, b| a < b),
+        "string-ci>?" => |args, _| compare("string-ci>?", args, true, |a, b| a > b),
+        "string-ci<=?" => |args, _| compare("string-ci<=?", args, true, |a, b| a <= b),
+        "string-ci>=?" => |args, _| compare("string-ci>=?", args, true, |a, b| a >= b),
+        "string-upcase" => |args, _| {
+            Ok(Value::String(
+                one_string("string-upcase", args)?.to_uppercase(),
+            ))
+        },
+        "string-downcase" => |args, _| {
+            Ok(Value::String(
+                one_string("string-downcase", args)?.to_lowercase(),
+            ))
+        },
+        "string-trim" => |args, _| {
+            Ok(Value::String(
+                one_string("string-trim", args)?.trim().to_string(),
+            ))
+        },
+        "string-trim-left" => |args, _| {
+            Ok(Value::String(
+                one_string("string-trim-left", args)?
+                    .trim_start()
+                    .to_string(),
+            ))
+        },
+        "string-trim-right" => |args, _| {
+            Ok(Value::String(
+                one_string("string-trim-right", args)?
+                    .trim_end()
+                    .to_string(),
+            ))
+        },
+        "string-index" => string_index,
+        "string-contains" => string_contains,
+        "string-split" => string_split,
+        "string-join" => string_join,
+        "string-pad" => |args, _| pad("string-pad", args, true),
+        "string-pad-right" => |args, _| pad("string-pad-right", args, false),
+        "string-map" => string_map,
+        "string-for-each" => string_for_each,
+        _ => return None,
+    };
+    Some(f)
+}
+
+fn one_string<'a>(name: &str, args: &'a [Value]) -> Result<&'a str, Exception> {
+    match args {
+        [Value::String(s)] => Ok(s),
+        _ => Err(format!(
+            "Expected one string argument to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+/// Reads an index argument, which must lie between 0 and `max` inclusive.
+/// The chars of a string between optional `start` and `end` indices.
+fn char_range(name: &str, s: &str, range: &[Value]) -> Result<Vec<char>, Exception> {
+    let chars = s.chars().collect::<Vec<char>>();
+    let (start, end) = range_args(name, range, chars.len())?;
+    Ok(chars[start..end].to_vec())
+}
+
+fn string_to_list(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::String(s)] => Ok(vec_to_list(s.chars().map(Value::Char).collect())),
+        [v] => Err(format!("Expected string argument to `string->list`, got {:#?}", v).into()),
+        _ => Err(format!("Expected one argument to `string->list`, got {:#?}", args).into()),
+    }
+}
+
+fn chars_to_string(name: &str, values: &[Value]) -> Result<String, Exception> {
+    values
+        .iter()
+        .map(|v| match v {
+            Value::Char(c) => Ok(*c),
+            v => Err(format!("Expected chars to `{}`, got {:#?}", name, v).into()),
+        })
+        .collect()
+}
+
+fn list_to_string(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [list] => {
+            let values = list_to_vec(list).ok_or_else(|| {
+                format!("Expected list argument to `list->string`, got {:#?}", list)
+            })?;
+            Ok(Value::String(chars_to_string("list->string", &values)?))
+        }
+        _ => Err(format!("Expected one argument to `list->string`, got {:#?}", args).into()),
+    }
+}
+
+fn string(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    Ok(Value::String(chars_to_string("string", args)?))
+}
+
+/// `(make-string k [char])` makes a string of `k` copies of `char`, or of spaces.
+fn make_string(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (k, c) = match args {
+        [Value::Integer(k)] if *k >= 0 => (*k as usize, ' '),
+        [Value::Integer(k), Value::Char(c)] if *k >= 0 => (*k as usize, *c),
+        _ => {
+            return Err(format!(
+                "Expected a length and optional char to `make-string`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    Ok(Value::String(std::iter::repeat_n(c, k).collect()))
+}
+
+fn string_length(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    Ok(Value::Integer(
+        one_string("string-length", args)?.chars().count() as isize,
+    ))
+}
+
+fn string_ref(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::String(s), k] => {
+            let chars = s.chars().collect::<Vec<char>>();
+            Ok(Value::Char(
+                chars[index_arg("string-ref", k, chars.len(), false)?],
+            ))
+        }
+        _ => Err(format!(
+            "Expected a string and an index to `string-ref`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(substring s start [end])`
+fn substring(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::String(s), range @ ..] if (1..=2).contains(&range.len()) => Ok(Value::String(
+            char_range("substring", s, range)?.iter().collect(),
+        )),
+        _ => Err(format!(
+            "Expected a string, start and optional end to `substring`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(string-copy s [start [end]])`
+fn string_copy(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::String(s), range @ ..] if range.len() <= 2 => Ok(Value::String(
+            char_range("string-copy", s, range)?.iter().collect(),
+        )),
+        _ => Err(format!(
+            "Expected a string and optional start and end to `string-copy`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn string_append(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    args.iter()
+        .map(|v| match v {
+            Value::String(s) => Ok(s.as_str()),
+            v => Err(format!("Expected string arguments to `string-append`, got {:#?}", v).into()),
+        })
+        .collect::<Result<String, Exception>>()
+        .map(Value::String)
+}
+
+/// True if every adjacent pair of strings satisfies `f`, comparing lowercased strings if `ci`.
+fn compare(
+    name: &str,
+    args: &[Value],
+    ci: bool,
+    f: fn(&str, &str) -> bool,
+) -> Result<Value, Exception> {
+    let strings = args
+        .iter()
+        .map(|v| match v {
+            Value::String(s) if ci => Ok(s.to_lowercase()),
+            Value::String(s) => Ok(s.clone()),
+            v => Err(format!("Expected string arguments to `{}`, got {:#?}", name, v).into()),
+        })
+        .collect::<Result<Vec<String>, Exception>>()?;
+    Ok(Value::Bool(strings.windows(2).all(|w| f(&w[0], &w[1]))))
+}
+
+/// `(string-index s pred)` finds the index of the first char equal to `pred`, or satisfying it
+/// if it is a procedure. Returns `#f` if there is none.
+fn string_index(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::String(s), pred] => {
+            for (i, c) in s.chars().enumerate() {
+                let found = match pred {
+                    Value::Char(p) => *p == c,
+                    f => !matches!(
+                        apply_procedure(f, &[Value::Char(c)], env)?,
+                        Value::Bool(false)
+                    ),
+                };
+                if found {
+                    return Ok(Value::Integer(i as isize));
+                }
+            }
+            Ok(Value::Bool(false))
+        }
+        _ => Err(format!(
+            "Expected a string and a char or predicate to `string-index`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(string-contains s pattern)` finds the index where `pattern` first occurs in `s`, or `#f`.
+fn string_contains(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::String(s), Value::String(pattern)] => Ok(match s.find(pattern.as_str()) {
+            Some(i) => Value::Integer(s[..i].chars().count() as isize),
+            None => Value::Bool(false),
+        }),
+        _ => Err(format!("Expected two strings to `string-contains`, got {:#?}", args).into()),
+    }
+}
+
+/// `(string-split s [delimiter])` splits on a char or string delimiter, or on runs of
+/// whitespace if none is given.
+fn string_split(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let parts: Vec<&str> = match args {
+        [Value::String(s)] => s.split_whitespace().collect(),
+        [Value::String(s), Value::Char(c)] => s.split(*c).collect(),
+        [Value::String(s), Value::String(d)] if !d.is_empty() => s.split(d.as_str()).collect(),
+        _ => {
+            return Err(format!(
+                "Expected a string and optional non-empty delimiter to `string-split`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    Ok(vec_to_list(
+        parts
+            .into_iter()
+            .map(|p| Value::String(p.to_string()))
+            .collect(),
+    ))
+}
+
+/// `(string-join list [delimiter])` concatenates a list of strings, separated by spaces or by
+/// `delimiter`.
+fn string_join(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (list, delimiter) = match args {
+        [list] => (list, " "),
+        [list, Value::String(d)] => (list, d.as_str()),
+        _ => {
+            return Err(format!(
+                "Expected a list and optional delimiter to `string-join`, got {:#?}",
+                args
+            )
+            .into())
+        }
+    };
+    let strings = list_to_vec(list)
+        .ok_or_else(|| format!("Expected list argument to `string-join`, got {:#?}", list))?
+        .into_iter()
+        .map(|v| match v {
+            Value::String(s) => Ok(s),
+            v => Err(format!("Expected list of strings to `string-join`, got {:#?}", v).into()),
+        })
+        .collect::<Result<Vec<String>, Exception>>()?;
+    Ok(Value::String(strings.join(delimiter)))
+}
+
+/// Pads a string to `k` chars, or truncates it, keeping the right end if `left` and the left
+/// end otherwise.
+fn pad(name: &str, args: &[Value], left: bool) -> Result<Value, Exception> {
+    let (s, k, c) = match args {
+        [Value::String(s), Value::Integer(k)] if *k >= 0 => (s, *k as usize, ' '),
+        [Value::String(s), Value::Integer(k), Value::Char(c)] if *k >= 0 => (s, *k as usize, *c),
+        _ => {
+            return Err(format!(
+                "Expected a string, length and optional char to `{}`, got {:#?}",
+                name, args
+            )
+            .into())
+        }
+    };
+    let chars = s.chars().collect::<Vec<char>>();
+    let res = if chars.len() >= k {
+        if left {
+            chars[chars.len() - k..].iter().collect()
+        } else {
+            chars[..k].iter().collect()
+        }
+    } else {
+        let padding = std::iter::repeat_n(c, k - chars.len());
+        if left {
+            padding.chain(chars).collect()
+        } else {
+            chars.into_iter().chain(padding).collect()
+        }
+    };
+    Ok(Value::String(res))
+}
+
+/// Splits `(proc s1 s2 ...)` arguments into the procedure and the strings' chars, truncated to
+/// the length of the shortest string.
+fn procedure_and_strings<'a>(
+    name: &str,
+    args: &'a [Value],
+) -> Result<(&'a Value, Vec<Vec<char>>), Exception> {
+    match args {
+        [f, strings @ ..] if !strings.is_empty() => {
+            let strings = strings
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => Ok(s.chars().collect::<Vec<char>>()),
+                    v => {
+                        Err(format!("Expected string arguments to `{}`, got {:#?}", name, v).into())
+                    }
+                })
+                .collect::<Result<Vec<Vec<char>>, Exception>>()?;
+            Ok((f, strings))
+        }
+        _ => Err(format!(
+            "Expected a procedure and at least one string to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+fn string_map(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, strings) = procedure_and_strings("string-map", args)?;
+    let len = strings.iter().map(|s| s.len()).min().unwrap_or(0);
+    let mut res = String::new();
+    for i in 0..len {
+        let chars = strings
+            .iter()
+            .map(|s| Value::Char(s[i]))
+            .collect::<Vec<Value>>();
+        match apply_procedure(f, &chars, env)? {
+            Value::Char(c) => res.push(c),
+            v => {
+                return Err(format!(
+                    "Expected procedure passed to `string-map` to return a char, got {:#?}",
+                    v
+                )
+                .into())
+            }
+        }
+    }
+    Ok(Value::String(res))
+}
+
+fn string_for_each(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, strings) = procedure_and_strings("string-for-each", args)?;
+    let len = strings.iter().map(|s| s.len()).min().unwrap_or(0);
+    for i in 0..len {
+        let chars = strings
+            .iter()
+            .map(|s| Value::Char(s[i]))
+            .collect::<Vec<Value>>();
+        apply_procedure(f, &chars, env)?;
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn indices_count_chars() {
+        assert_eq!(
+            run("(list (string-length \"λx\") (string-ref \"λx\" 1))"),
+            Ok("(2 #\\x)".into())
+        );
+        assert_eq!(
+            run("(list (substring \"hello world\" 6) (substring \"hello\" 1 3) (string-copy \"hello\" 1 3))"),
+            Ok("(\"world\" \"el\" \"el\")".into())
+        );
+        assert!(run("(substring \"abc\" 2 1)").is_err());
+        assert!(run("(string-ref \"abc\" 3)").is_err());
+    }
+
+    #[test]
+    fn searching() {
+        assert_eq!(
+            run("(list (string-index \"hello\" #\\l) (string-index \"hello\" char-upper-case?))"),
+            Ok("(2 #f)".into())
+        );
+        assert_eq!(
+            run("(list (string-contains \"hello\" \"llo\") (string-contains \"hello\" \"z\"))"),
+            Ok("(2 #f)".into())
+        );
+    }
+
+    #[test]
+    fn splitting_joining_and_padding() {
+        assert_eq!(
+            run("(list (string-split \"a,b\" #\\,) (string-split \" a  b \"))"),
+            Ok("((\"a\" \"b\") (\"a\" \"b\"))".into())
+        );
+        assert_eq!(
+            run("(string-join (list \"a\" \"b\") \", \")"),
+            Ok("\"a, b\"".into())
+        );
+        assert_eq!(
+            run("(list (string-pad \"42\" 5 #\\0) (string-pad \"12345\" 3) (string-pad-right \"ab\" 4))"),
+            Ok("(\"00042\" \"345\" \"ab  \")".into())
+        );
+    }
+
+    #[test]
+    fn case_and_comparisons() {
+        assert_eq!(
+            run("(list (string-upcase \"abc\") (string-trim \"  hi  \") (string-map char-upcase \"hi\"))"),
+            Ok("(\"ABC\" \"hi\" \"HI\")".into())
+        );
+        assert_eq!(
+            run("(list (string<? \"a\" \"b\" \"c\") (string<? \"b\" \"a\") (string-ci=? \"AbC\" \"abc\"))"),
+            Ok("(#t #f #t)".into())
+        );
+    }
+}
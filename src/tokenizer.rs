@@ -1,6 +1,13 @@
+use crate::eval::Value;
+use crate::numeric;
+use std::iter::Peekable;
+use std::str::Chars;
+
 #[derive(Clone, Debug)]
 pub enum TokenType {
     OpenParen,
+    VectorOpen,
+    BytevectorOpen,
     CloseParen,
     Lambda,
     Define,
@@ -11,13 +18,14 @@ pub enum TokenType {
     Identifier(String),
     Number(f64),
     Integer(isize),
+    Complex(f64, f64),
     Char(char),
     String(String),
 }
 
 #[derive(Debug)]
 pub struct TokenIterator {
-    data: Vec<String>,
+    data: Vec<TokenType>,
     index: usize,
 }
 
@@ -34,52 +42,178 @@ impl Iterator for TokenIterator {
 
 impl TokenIterator {
     pub fn get_state(&self) -> Option<TokenType> {
-        if self.index >= self.data.len() {
-            return None;
+        self.data.get(self.index).cloned()
+    }
+}
+
+/// Reads the character named by a `#\` literal, e.g. `a`, `space` or `x3bb`.
+fn parse_char(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    if chars.next().is_none() {
+        return Some(first);
+    }
+    match name {
+        "space" => Some(' '),
+        "newline" | "linefeed" => Some('\n'),
+        "tab" => Some('\t'),
+        "return" => Some('\r'),
+        "null" | "nul" => Some('\0'),
+        "alarm" => Some('\u{7}'),
+        "backspace" => Some('\u{8}'),
+        "escape" | "altmode" => Some('\u{1b}'),
+        "delete" | "rubout" => Some('\u{7f}'),
+        _ => {
+            let hex = name.strip_prefix('x').or_else(|| name.strip_prefix('X'))?;
+            u32::from_str_radix(hex, 16)
+                .ok()
+                .and_then(std::char::from_u32)
+        }
+    }
+}
+
+fn to_token(current: &str) -> Result<TokenType, String> {
+    if let Some(name) = current.strip_prefix("#\\") {
+        return parse_char(name)
+            .map(TokenType::Char)
+            .ok_or(format!("Invalid character literal {}", current));
+    } else if let Some(s) = current.strip_prefix('"') {
+        return Ok(TokenType::String(s.to_string()));
+    } else if let Some(s) = current.strip_prefix('|') {
+        return Ok(TokenType::Identifier(s.to_string()));
+    } else if numeric::radix_prefix(current).is_some() {
+        // Only `#d` numbers can be inexact or complex, which `parse_number` sees to
+        return number_token(current).ok_or(format!("Invalid number literal {}", current));
+    }
+    Ok(match current {
+        "'" => TokenType::SingleQuote,
+        "(" => TokenType::OpenParen,
+        "#(" => TokenType::VectorOpen,
+        "#u8(" => TokenType::BytevectorOpen,
+        ")" => TokenType::CloseParen,
+        "#t" => TokenType::True,
+        "#f" => TokenType::False,
+        s => match number_token(s) {
+            Some(token) => token,
+            None => match s {
+                "lambda" => TokenType::Lambda,
+                "define" => TokenType::Define,
+                "if" => TokenType::If,
+                _ => TokenType::Identifier(s.to_string()),
+            },
+        },
+    })
+}
+
+fn number_token(s: &str) -> Option<TokenType> {
+    match numeric::parse_number(s, 10)? {
+        Value::Integer(n) => Some(TokenType::Integer(n)),
+        Value::Number(n) => Some(TokenType::Number(n)),
+        Value::Complex(re, im) => Some(TokenType::Complex(re, im)),
+        _ => None,
+    }
+}
+
+const UNTERMINATED_STRING: &str = "Unterminated string literal!";
+const UNTERMINATED_SYMBOL: &str = "Unterminated |symbol|!";
+
+/// Whether `s` opens more parentheses than it closes or ends inside a string, and so needs
+/// more input before it can be parsed.
+pub fn is_incomplete(s: &str) -> bool {
+    match split(s) {
+        Ok(tokens) => {
+            tokens.iter().fold(0, |depth, token| match token.as_str() {
+                "(" | "#(" | "#u8(" => depth + 1,
+                ")" => depth - 1,
+                _ => depth,
+            }) > 0
         }
-        let current = &self.data[self.index];
-        if current.starts_with("#\\") {
-            let mut it = current.chars().skip(2);
-            let c = it.next();
-            if it.next().is_none() {
-                if let Some(c) = c {
-                    return Some(TokenType::Char(c));
+        Err(msg) => msg == UNTERMINATED_STRING || msg == UNTERMINATED_SYMBOL,
+    }
+}
+
+pub fn tokenize(s: &str) -> Result<TokenIterator, String> {
+    let data = split(s)?
+        .iter()
+        .map(|token| to_token(token))
+        .collect::<Result<Vec<TokenType>, String>>()?;
+    Ok(TokenIterator { data, index: 0 })
+}
+
+/// Reads the escape sequence following a `\\` in a string literal, returning `None` for a
+/// line continuation.
+fn read_escape(chars: &mut Peekable<Chars>) -> Result<Option<char>, String> {
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return Err(UNTERMINATED_STRING.to_string()),
+    };
+    Ok(Some(match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        'a' => '\u{7}',
+        'b' => '\u{8}',
+        '0' => '\0',
+        '\\' => '\\',
+        '"' => '"',
+        '|' => '|',
+        'x' | 'X' => {
+            let mut hex = String::new();
+            loop {
+                match chars.next() {
+                    Some(';') => break,
+                    Some(c) if c.is_ascii_hexdigit() => hex.push(c),
+                    _ => return Err(format!("Invalid hex escape \\x{} in string!", hex)),
                 }
-            } else {
-                return None;
             }
-        } else if current.starts_with("\"") {
-            return Some(TokenType::String(current.clone()));
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .and_then(std::char::from_u32)
+                .ok_or(format!("Invalid hex escape \\x{}; in string!", hex))?
         }
-        match current.as_str() {
-            "'" => Some(TokenType::SingleQuote),
-            "(" => Some(TokenType::OpenParen),
-            ")" => Some(TokenType::CloseParen),
-            "#t" => Some(TokenType::True),
-            "#f" => Some(TokenType::False),
-            s => {
-                if let Ok(n) = s.parse::<isize>() {
-                    Some(TokenType::Integer(n))
-                } else {
-                    if let Ok(n) = s.parse::<f64>() {
-                        Some(TokenType::Number(n))
-                    } else {
-                        match s {
-                            "lambda" => Some(TokenType::Lambda),
-                            "define" => Some(TokenType::Define),
-                            "if" => Some(TokenType::If),
-                            _ => Some(TokenType::Identifier(s.to_string())),
-                        }
-                    }
+        c if c == '\n' || c == ' ' || c == '\t' || c == '\r' => {
+            let mut seen_newline = c == '\n';
+            while let Some(&c) = chars.peek() {
+                if c == '\n' && !seen_newline {
+                    seen_newline = true;
+                } else if c != ' ' && c != '\t' && c != '\r' {
+                    break;
+                }
+                chars.next();
+            }
+            if !seen_newline {
+                return Err("Expected a newline after \\ in string!".to_string());
+            }
+            return Ok(None);
+        }
+        c => return Err(format!("Unknown escape sequence \\{} in string!", c)),
+    }))
+}
+
+/// Reads the rest of a `|...|` symbol, which can hold any chars, with the same escapes as strings
+/// and `\|` for a bar. The token keeps its opening bar to tell it apart from other identifiers.
+fn read_bar_symbol(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut s = String::from("|");
+    loop {
+        match chars.next() {
+            Some('|') => return Ok(s),
+            Some('\\') if chars.next_if_eq(&'|').is_some() => s.push('|'),
+            Some('\\') => {
+                if let Some(c) = read_escape(chars)? {
+                    s.push(c);
                 }
             }
+            Some(c) => s.push(c),
+            None => return Err(UNTERMINATED_SYMBOL.to_string()),
         }
     }
 }
 
-pub fn tokenize(s: &String) -> TokenIterator {
+/// Splits source text into the strings making up each token. String tokens keep their opening
+/// quote to tell them apart from identifiers, as `|...|` symbols keep their opening bar.
+fn split(s: &str) -> Result<Vec<String>, String> {
     let mut v = Vec::new();
-    let mut chars = s.chars();
+    let mut chars = s.chars().peekable();
     let mut tempstr = String::new();
     let mut in_string = false;
     loop {
@@ -87,13 +221,34 @@ pub fn tokenize(s: &String) -> TokenIterator {
         match c {
             Some(c) => {
                 if !in_string {
-                    if c == '"' {
+                    if tempstr == "#\\" {
+                        tempstr.push(c);
+                    } else if c == ';' {
+                        // A comment runs to the end of the line
+                        if !tempstr.is_empty() {
+                            v.push(tempstr);
+                            tempstr = String::new();
+                        }
+                        while chars.next_if(|&c| c != '\n').is_some() {}
+                    } else if c == '"' {
                         in_string = true;
                         if !tempstr.is_empty() {
                             v.push(tempstr);
                             tempstr = String::new();
                         }
                         tempstr.push('"');
+                    } else if c == '|' {
+                        if !tempstr.is_empty() {
+                            v.push(tempstr);
+                            tempstr = String::new();
+                        }
+                        v.push(read_bar_symbol(&mut chars)?);
+                    } else if c == '\'' && tempstr.is_empty() {
+                        v.push(String::from("'"));
+                    } else if c == '(' && (tempstr == "#" || tempstr == "#u8") {
+                        tempstr.push('(');
+                        v.push(tempstr);
+                        tempstr = String::new();
                     } else if c == '(' {
                         if !tempstr.is_empty() {
                             v.push(tempstr);
@@ -114,15 +269,12 @@ pub fn tokenize(s: &String) -> TokenIterator {
                     }
                 } else {
                     match c {
-                        '\\' => match chars.next() {
-                            Some('n') => tempstr.push('\n'),
-                            Some('t') => tempstr.push('\t'),
-                            Some('\\') => tempstr.push('\\'),
-                            Some('"') => tempstr.push('"'),
-                            _ => (),
-                        },
+                        '\\' => {
+                            if let Some(c) = read_escape(&mut chars)? {
+                                tempstr.push(c);
+                            }
+                        }
                         '"' => {
-                            tempstr.push('"');
                             v.push(tempstr);
                             tempstr = String::new();
                             in_string = false;
@@ -134,8 +286,66 @@ pub fn tokenize(s: &String) -> TokenIterator {
             None => break,
         }
     }
+    if in_string {
+        return Err(UNTERMINATED_STRING.to_string());
+    }
     if !tempstr.is_empty() {
         v.push(tempstr);
     }
-    TokenIterator { data: v, index: 0 }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokenize;
+
+    /// The tokens of `s` as their debug output, separated by spaces.
+    fn tokens(s: &str) -> Result<String, String> {
+        let tokens = tokenize(s)?;
+        Ok(tokens
+            .data
+            .iter()
+            .map(|t| format!("{:?}", t))
+            .collect::<Vec<String>>()
+            .join(" "))
+    }
+
+    #[test]
+    fn radix_prefixes() {
+        assert_eq!(
+            tokens("#xff #b1010 #o17"),
+            Ok("Integer(255) Integer(10) Integer(15)".into())
+        );
+        assert_eq!(
+            tokens("#d10 #d1.5 #d1e3"),
+            Ok("Integer(10) Number(1.5) Number(1000.0)".into())
+        );
+        assert_eq!(tokens("#d1+2i"), Ok("Complex(1.0, 2.0)".into()));
+        assert!(tokens("#x1.5").is_err());
+        assert!(tokens("#b102").is_err());
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            tokens(r#""a\nb\t\\\"" "\x3bb;" "\a\b\r\0""#),
+            Ok(r#"String("a\nb\t\\\"") String("λ") String("\u{7}\u{8}\r\0")"#.into())
+        );
+        assert_eq!(
+            tokens("\"one \\\n    two\""),
+            Ok(r#"String("one two")"#.into())
+        );
+        assert!(tokens(r#""\q""#).is_err());
+        assert!(tokens(r#""\x3bb""#).is_err());
+        assert!(tokens(r#""open"#).is_err());
+    }
+
+    #[test]
+    fn bar_symbols() {
+        assert_eq!(
+            tokens(r"|a b| |a\|b| || |\x3bb;|"),
+            Ok(r#"Identifier("a b") Identifier("a|b") Identifier("") Identifier("λ")"#.into())
+        );
+        assert!(tokens("|open").is_err());
+    }
 }
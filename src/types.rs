@@ -0,0 +1,203 @@
+use crate::eval::{list_to_vec, Builtin, Value};
+use crate::exceptions::Exception;
+use crate::ports::Port;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "number?" => |args, _| predicate("number?", args, is_number),
+        "complex?" => |args, _| predicate("complex?", args, is_number),
+        "real?" => |args, _| {
+            predicate("real?", args, |v| {
+                matches!(v, Value::Integer(_) | Value::Number(_))
+            })
+        },
+        "rational?" => |args, _| {
+            predicate("rational?", args, |v| match v {
+                Value::Integer(_) => true,
+                Value::Number(n) => n.is_finite(),
+                _ => false,
+            })
+        },
+        "integer?" => |args, _| {
+            predicate("integer?", args, |v| match v {
+                Value::Integer(_) => true,
+                Value::Number(n) => n.is_finite() && n.fract() == 0.0,
+                _ => false,
+            })
+        },
+        "exact-integer?" => {
+            |args, _| predicate("exact-integer?", args, |v| matches!(v, Value::Integer(_)))
+        }
+        "exact?" => |args, _| exactness("exact?", args, true),
+        "inexact?" => |args, _| exactness("inexact?", args, false),
+        "boolean?" => |args, _| predicate("boolean?", args, |v| matches!(v, Value::Bool(_))),
+        "string?" => |args, _| predicate("string?", args, |v| matches!(v, Value::String(_))),
+        "symbol?" => |args, _| predicate("symbol?", args, |v| matches!(v, Value::Symbol(_))),
+        "pair?" => |args, _| predicate("pair?", args, |v| matches!(v, Value::Pair(_))),
+        "list?" => |args, _| predicate("list?", args, |v| list_to_vec(v).is_some()),
+        "vector?" => |args, _| predicate("vector?", args, |v| matches!(v, Value::Vector(_))),
+        "bytevector?" => {
+            |args, _| predicate("bytevector?", args, |v| matches!(v, Value::Bytevector(_)))
+        }
+        "procedure?" => |args, _| predicate("procedure?", args, is_procedure),
+        "record?" => |args, _| predicate("record?", args, |v| matches!(v, Value::Record(_))),
+        "port?" => |args, _| predicate("port?", args, |v| matches!(v, Value::Port(_))),
+        "environment?" => {
+            |args, _| predicate("environment?", args, |v| matches!(v, Value::Environment(_)))
+        }
+        "input-port?" => |args, _| {
+            predicate("input-port?", args, |v| match v {
+                Value::Port(p) => matches!(*p.borrow(), Port::Input(_)),
+                _ => false,
+            })
+        },
+        "output-port?" => |args, _| {
+            predicate("output-port?", args, |v| match v {
+                Value::Port(p) => matches!(*p.borrow(), Port::Output(_)),
+                _ => false,
+            })
+        },
+        "not" => |args, _| match args {
+            [v] => Ok(Value::Bool(matches!(v, Value::Bool(false)))),
+            _ => Err(format!("Expected one argument to `not`, got {:#?}", args).into()),
+        },
+        "type-of" => |args, _| match args {
+            [v] => Ok(Value::Symbol(type_of(v))),
+            _ => Err(format!("Expected one argument to `type-of`, got {:#?}", args).into()),
+        },
+        _ => return None,
+    };
+    Some(f)
+}
+
+/// The name of the type of `v`, as returned by `type-of`. Records give the name of their
+/// record type.
+pub fn type_of(v: &Value) -> String {
+    let name = match v {
+        Value::Integer(_) => "integer",
+        Value::Number(_) => "real",
+        Value::Complex(_, _) => "complex",
+        Value::Bool(_) => "boolean",
+        Value::Char(_) => "char",
+        Value::String(_) => "string",
+        Value::Symbol(_) => "symbol",
+        Value::Pair(_) => "pair",
+        Value::Nil => "null",
+        Value::Vector(_) => "vector",
+        Value::Bytevector(_) => "bytevector",
+        Value::HashTable(_) => "hash-table",
+        Value::PMap(_) => "pmap",
+        Value::PSet(_) => "pset",
+        Value::PVector(_) => "pvector",
+        Value::Record(r) => r.record_type.short_name(),
+        Value::Port(_) => "port",
+        Value::Eof => "eof",
+        Value::Environment(_) => "environment",
+        Value::Error(_) => "error",
+        Value::Function(_, _) | Value::Builtin(_) | Value::RecordProcedure(_) => "procedure",
+    };
+    name.to_string()
+}
+
+pub fn is_procedure(v: &Value) -> bool {
+    matches!(
+        v,
+        Value::Function(_, _) | Value::Builtin(_) | Value::RecordProcedure(_)
+    )
+}
+
+fn is_number(v: &Value) -> bool {
+    matches!(
+        v,
+        Value::Integer(_) | Value::Number(_) | Value::Complex(_, _)
+    )
+}
+
+fn predicate(name: &str, args: &[Value], f: fn(&Value) -> bool) -> Result<Value, Exception> {
+    match args {
+        [v] => Ok(Value::Bool(f(v))),
+        _ => Err(format!("Expected one argument to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+/// `exact?` and `inexact?`, which only make sense for numbers. Integers are the only exact
+/// numbers.
+fn exactness(name: &str, args: &[Value], exact: bool) -> Result<Value, Exception> {
+    match args {
+        [Value::Integer(_)] => Ok(Value::Bool(exact)),
+        [Value::Number(_)] | [Value::Complex(_, _)] => Ok(Value::Bool(!exact)),
+        _ => Err(format!("Expected one number to `{}`, got {:#?}", name, args).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    /// An expression for a value of each type, with the name `type-of` gives it and its
+    /// predicate.
+    const SAMPLES: &[(&str, &str, &str)] = &[
+        ("1", "integer", "exact-integer?"),
+        ("1.5", "real", "real?"),
+        ("(make-rectangular 1 2)", "complex", "complex?"),
+        ("#t", "boolean", "boolean?"),
+        ("#\\a", "char", "char?"),
+        ("\"a\"", "string", "string?"),
+        ("'a", "symbol", "symbol?"),
+        ("(list 1)", "pair", "pair?"),
+        ("'()", "null", "null?"),
+        ("(vector 1)", "vector", "vector?"),
+        ("(bytevector 1)", "bytevector", "bytevector?"),
+        ("(make-hash-table)", "hash-table", "hash-table?"),
+        ("(pmap)", "pmap", "pmap?"),
+        ("(pset)", "pset", "pset?"),
+        ("(pvector)", "pvector", "pvector?"),
+        ("(open-input-string \"\")", "port", "port?"),
+        ("(eof-object)", "eof", "eof-object?"),
+        ("(sandbox-environment)", "environment", "environment?"),
+        ("(interaction-environment)", "environment", "environment?"),
+        (
+            "(guard (e (#t e)) (error \"oops\"))",
+            "error",
+            "error-object?",
+        ),
+        ("car", "procedure", "procedure?"),
+        ("(lambda (x) x)", "procedure", "procedure?"),
+    ];
+
+    #[test]
+    fn type_of_agrees_with_predicates() {
+        for (expr, name, predicate) in SAMPLES {
+            assert_eq!(run(&format!("(type-of {})", expr)), Ok(name.to_string()));
+            assert_eq!(
+                run(&format!("({} {})", predicate, expr)),
+                Ok("#t".into()),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn not_is_a_procedure() {
+        assert_eq!(
+            run("(list (not #f) (not #t) (not 0) (not '()))"),
+            Ok("(#t #f #f #f)".into())
+        );
+        assert_eq!(run("(map not (list #t #f 1))"), Ok("(#f #t #f)".into()));
+        assert_eq!(run("(procedure? not)"), Ok("#t".into()));
+        assert!(run("(not 1 2)").is_err());
+    }
+
+    #[test]
+    fn predicates_reject_other_types() {
+        assert_eq!(
+            run("(list (environment? 1) (procedure? 'car) (list? (cons 1 2)) (integer? 1.5))"),
+            Ok("(#f #f #f #f)".into())
+        );
+        assert_eq!(
+            run("(list (integer? 2.0) (rational? 1.5))"),
+            Ok("(#t #t)".into())
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use crate::eval::{
+    apply_procedure, index_arg, list_to_vec, range_args, vec_to_list, Builtin, Value,
+};
+use crate::exceptions::Exception;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let f: Builtin = match name {
+        "make-vector" => make_vector,
+        "vector" => |args, _| Ok(new_vector(args.to_vec())),
+        "vector-length" => vector_length,
+        "vector-ref" => vector_ref,
+        "vector-set!" => vector_set,
+        "vector->list" => vector_to_list,
+        "list->vector" => list_to_vector,
+        "vector-fill!" => vector_fill,
+        "vector-copy" => vector_copy,
+        "vector-map" => vector_map,
+        "vector-for-each" => vector_for_each,
+        _ => return None,
+    };
+    Some(f)
+}
+
+pub fn new_vector(elements: Vec<Value>) -> Value {
+    Value::Vector(Rc::new(RefCell::new(elements)))
+}
+
+/// Checks that `v` is an index into a vector of length `len`. `end` allows `len` itself, for
+/// the end of a range.
+/// `(make-vector k [fill])`
+fn make_vector(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Integer(k)] if *k >= 0 => Ok(new_vector(vec![Value::Nil; *k as usize])),
+        [Value::Integer(k), fill] if *k >= 0 => Ok(new_vector(vec![fill.clone(); *k as usize])),
+        _ => Err(format!(
+            "Expected a length and optional fill to `make-vector`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn vector_length(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Vector(v)] => Ok(Value::Integer(v.borrow().len() as isize)),
+        _ => Err(format!(
+            "Expected one vector argument to `vector-length`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn vector_ref(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Vector(v), k] => {
+            let v = v.borrow();
+            Ok(v[index_arg("vector-ref", k, v.len(), false)?].clone())
+        }
+        _ => Err(format!(
+            "Expected a vector and an index to `vector-ref`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn vector_set(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Vector(v), k, x] => {
+            let mut v = v.borrow_mut();
+            let k = index_arg("vector-set!", k, v.len(), false)?;
+            v[k] = x.clone();
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a vector, an index and a value to `vector-set!`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(vector->list v [start [end]])`
+fn vector_to_list(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Vector(v), rest @ ..] if rest.len() <= 2 => {
+            let v = v.borrow();
+            let (start, end) = range_args("vector->list", rest, v.len())?;
+            Ok(vec_to_list(v[start..end].to_vec()))
+        }
+        _ => Err(format!(
+            "Expected a vector and optional start and end to `vector->list`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+fn list_to_vector(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [list] => list_to_vec(list).map(new_vector).ok_or_else(|| {
+            format!("Expected list argument to `list->vector`, got {:#?}", list).into()
+        }),
+        _ => Err(format!("Expected one argument to `list->vector`, got {:#?}", args).into()),
+    }
+}
+
+/// `(vector-fill! v x [start [end]])`
+fn vector_fill(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Vector(v), x, rest @ ..] if rest.len() <= 2 => {
+            let mut v = v.borrow_mut();
+            let (start, end) = range_args("vector-fill!", rest, v.len())?;
+            for element in &mut v[start..end] {
+                *element = x.clone();
+            }
+            Ok(Value::Nil)
+        }
+        _ => Err(format!(
+            "Expected a vector, a value and optional start and end to `vector-fill!`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// `(vector-copy v [start [end]])` makes a new vector with the same elements.
+fn vector_copy(args: &[Value], _: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    match args {
+        [Value::Vector(v), rest @ ..] if rest.len() <= 2 => {
+            let v = v.borrow();
+            let (start, end) = range_args("vector-copy", rest, v.len())?;
+            Ok(new_vector(v[start..end].to_vec()))
+        }
+        _ => Err(format!(
+            "Expected a vector and optional start and end to `vector-copy`, got {:#?}",
+            args
+        )
+        .into()),
+    }
+}
+
+/// Splits `(proc v1 v2 ...)` arguments into the procedure and copies of the vectors' elements,
+/// so that the procedure is free to modify the vectors.
+fn procedure_and_vectors<'a>(
+    name: &str,
+    args: &'a [Value],
+) -> Result<(&'a Value, Vec<Vec<Value>>), Exception> {
+    match args {
+        [f, vectors @ ..] if !vectors.is_empty() => {
+            let vectors = vectors
+                .iter()
+                .map(|v| match v {
+                    Value::Vector(v) => Ok(v.borrow().clone()),
+                    v => {
+                        Err(format!("Expected vector arguments to `{}`, got {:#?}", name, v).into())
+                    }
+                })
+                .collect::<Result<Vec<Vec<Value>>, Exception>>()?;
+            Ok((f, vectors))
+        }
+        _ => Err(format!(
+            "Expected a procedure and at least one vector to `{}`, got {:#?}",
+            name, args
+        )
+        .into()),
+    }
+}
+
+fn vector_map(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, vectors) = procedure_and_vectors("vector-map", args)?;
+    let len = vectors.iter().map(|v| v.len()).min().unwrap_or(0);
+    let mut res = Vec::with_capacity(len);
+    for i in 0..len {
+        let elements = vectors.iter().map(|v| v[i].clone()).collect::<Vec<Value>>();
+        res.push(apply_procedure(f, &elements, env)?);
+    }
+    Ok(new_vector(res))
+}
+
+fn vector_for_each(args: &[Value], env: &mut HashMap<String, Value>) -> Result<Value, Exception> {
+    let (f, vectors) = procedure_and_vectors("vector-for-each", args)?;
+    let len = vectors.iter().map(|v| v.len()).min().unwrap_or(0);
+    for i in 0..len {
+        let elements = vectors.iter().map(|v| v[i].clone()).collect::<Vec<Value>>();
+        apply_procedure(f, &elements, env)?;
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::run;
+
+    #[test]
+    fn literals_are_not_evaluated() {
+        assert_eq!(run("#(a (b) \"c\")"), Ok("#(a (b) \"c\")".into()));
+        assert_eq!(run("(vector-ref #(1 2 3) 0)"), Ok("1".into()));
+    }
+
+    #[test]
+    fn vectors_are_shared() {
+        assert_eq!(
+            run("(define v (vector 1 2)) (define w v) (vector-set! w 0 9) v"),
+            Ok("#(9 2)".into())
+        );
+        assert_eq!(
+            run("(define u (make-vector 2 0)) (vector-fill! u 7) u"),
+            Ok("#(7 7)".into())
+        );
+    }
+
+    #[test]
+    fn conversions_and_mapping() {
+        assert_eq!(
+            run("(list (vector->list #(1 2 3) 1) (list->vector (list 1 2)) (vector-copy #(1 2 3) 1))"),
+            Ok("((2 3) #(1 2) #(2 3))".into())
+        );
+        assert_eq!(run("(vector-map + #(1 2) #(10 20))"), Ok("#(11 22)".into()));
+        assert!(run("(vector-ref #(1 2) 2)").is_err());
+        assert!(run("(vector-set! #(1 2) -1 0)").is_err());
+    }
+}
@@ -10,7 +10,16 @@
 (display msg)
 (newline)
 (display "Here it is backwards!")
+(newline)
 (display (list->string (reverse (string->list msg))))
 (newline)
 (display "This is a\ttab")
+(newline)
 (display "What a \"cool\" programming language this is")
+(newline)
+(display (string-append "Shouting: " (string-upcase msg)))
+(newline)
+(display (string-join (string-split "split me into words") "-"))
+(newline)
+(display (string-map (lambda (c) (if (char=? c #\space) #\_ c)) "no more spaces"))
+(newline)